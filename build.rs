@@ -7,10 +7,16 @@ use std::path::{Path, PathBuf};
 struct LwPktOptions {
     max_data_len: Option<usize>,
     use_flags: bool,
+    no_addr: bool,
+    no_crc: bool,
+    start_byte: Option<u8>,
+    stop_byte: Option<u8>,
 }
 
 impl LwPktOptions {
     const END_FILE: &str = "#endif /* LWPKT_OPTS_HDR_H */";
+    const START_BYTE: u8 = 0xAA;
+    const STOP_BYTE: u8 = 0x55;
 
     fn generate(self, path: &Path) {
         let mut origin_f = File::open("lwpkt_opts_template.h").unwrap();
@@ -31,19 +37,128 @@ impl LwPktOptions {
         }
 
         if self.use_flags {
-            f.write_all("\n#define LWPKT_CFG_USE_FLAGS\n".as_bytes())
+            f.write_all("\n#define LWPKT_CFG_USE_FLAGS 1\n".as_bytes())
                 .unwrap();
         }
 
+        if self.no_addr {
+            f.write_all("\n#define LWPKT_CFG_USE_ADDR 0\n".as_bytes())
+                .unwrap();
+        }
+
+        if self.no_crc {
+            f.write_all("\n#define LWPKT_CFG_USE_CRC 0\n".as_bytes())
+                .unwrap();
+        }
+
+        // Always defined so the bindings expose the bytes in use
+        let start = self.start_byte.unwrap_or(Self::START_BYTE);
+        let stop = self.stop_byte.unwrap_or(Self::STOP_BYTE);
+        f.write_all(format!("\n#define LWPKT_CFG_START_BYTE 0x{start:02X}\n").as_bytes())
+            .unwrap();
+        f.write_all(format!("\n#define LWPKT_CFG_STOP_BYTE 0x{stop:02X}\n").as_bytes())
+            .unwrap();
+
         f.write_all(Self::END_FILE.as_bytes()).unwrap();
         f.flush().unwrap();
     }
+
+    /// lwpkt.c hardcodes the framing bytes, point them at the `LWPKT_CFG_*` macros
+    fn patch_source(path: &Path) {
+        let source = std::fs::read_to_string(path).unwrap();
+
+        let mut patched = String::with_capacity(source.len());
+        let mut found = 0;
+        for line in source.lines() {
+            let define = line.split_whitespace().take(2).collect::<Vec<_>>();
+            match define.as_slice() {
+                ["#define", "LWPKT_START_BYTE"] => {
+                    patched.push_str("#define LWPKT_START_BYTE LWPKT_CFG_START_BYTE");
+                    found += 1;
+                }
+                ["#define", "LWPKT_STOP_BYTE"] => {
+                    patched.push_str("#define LWPKT_STOP_BYTE LWPKT_CFG_STOP_BYTE");
+                    found += 1;
+                }
+                _ => patched.push_str(line),
+            }
+            patched.push('\n');
+        }
+        assert_eq!(found, 2, "start/stop byte defines not found in lwpkt.c");
+
+        std::fs::write(path, patched).unwrap();
+    }
+}
+
+fn parse_byte(name: &str) -> Option<u8> {
+    let v = std::env::var(name).ok()?;
+    let parsed = match v.strip_prefix("0x").or_else(|| v.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => v.parse::<u8>(),
+    };
+
+    Some(parsed.unwrap_or_else(|_| panic!("{name} must be a byte, got {v:?}")))
+}
+
+/// `"version"` field of the `library.json` shipped with the C sources
+fn library_version(path: &str) -> String {
+    let Ok(manifest) = std::fs::read_to_string(path) else {
+        return "unknown".to_string();
+    };
+    println!("cargo:rerun-if-changed={path}");
+
+    manifest
+        .split_once("\"version\"")
+        .and_then(|(_, rest)| rest.split('"').nth(1))
+        .unwrap_or("unknown")
+        .to_string()
+}
+
+/// Refuses to switch a submodule with local changes, they would be carried over or lost
+/// depending on the branch
+fn checkout_lwpkt(branch: &str) {
+    let status = lwpkt_git(&["status", "--porcelain", "--untracked-files=no"]);
+    assert!(
+        status.status.success(),
+        "git status failed in src/lwpkt: {}",
+        String::from_utf8_lossy(&status.stderr)
+    );
+
+    if !status.stdout.is_empty() {
+        println!("cargo:warning=src/lwpkt has local changes, not checking out {branch}");
+        panic!(
+            "LWPKT_BRANCH={branch} is set but src/lwpkt has local changes, commit or stash them first:\n{}",
+            String::from_utf8_lossy(&status.stdout)
+        );
+    }
+
+    let checkout = lwpkt_git(&["checkout", "-q", branch]);
+    assert!(
+        checkout.status.success(),
+        "git checkout {branch} failed in src/lwpkt: {}",
+        String::from_utf8_lossy(&checkout.stderr)
+    );
+}
+
+fn lwpkt_git(args: &[&str]) -> std::process::Output {
+    std::process::Command::new("git")
+        .args([
+            "--work-tree",
+            "./src/lwpkt",
+            "--git-dir",
+            "./src/lwpkt/.git",
+        ])
+        .args(args)
+        .output()
+        .unwrap()
 }
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=lwpkt_opts_template.h");
     println!("cargo:rerun-if-env-changed=LWPKT_CFG_MAX_DATA_LEN");
+    println!("cargo:rerun-if-env-changed=LWPKT_CFG_START_BYTE");
+    println!("cargo:rerun-if-env-changed=LWPKT_CFG_STOP_BYTE");
     println!("cargo:rerun-if-env-changed=LWPKT_BRANCH");
 
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
@@ -65,26 +180,36 @@ fn main() {
         options.max_data_len = Some(v.to_str().unwrap().parse::<usize>().unwrap());
     }
 
+    options.start_byte = parse_byte("LWPKT_CFG_START_BYTE");
+    options.stop_byte = parse_byte("LWPKT_CFG_STOP_BYTE");
+
     if std::env::var_os("CARGO_FEATURE_FLAGS").is_some() {
         options.use_flags = true;
     }
 
+    if std::env::var_os("CARGO_FEATURE_NO_ADDR").is_some() {
+        options.no_addr = true;
+    }
+
+    // lwpkt has a single LWPKT_CFG_USE_CRC for both directions, there is no way to only
+    // append or only check the CRC
+    if std::env::var_os("CARGO_FEATURE_NO_CRC").is_some() {
+        options.no_crc = true;
+    }
+
     if let Ok(branch) = std::env::var("LWPKT_BRANCH") {
-        let cmd = std::process::Command::new("git")
-            .args(&[
-                "--work-tree",
-                "./src/lwpkt",
-                "--git-dir",
-                "./src/lwpkt/.git",
-                "checkout",
-                "-q",
-                &branch,
-            ])
-            .status()
-            .unwrap();
-        assert!(cmd.success());
+        checkout_lwpkt(&branch);
     }
 
+    println!(
+        "cargo:rustc-env=LWPKT_LIB_VERSION={}",
+        library_version("src/lwpkt/library.json")
+    );
+    println!(
+        "cargo:rustc-env=LWRB_LIB_VERSION={}",
+        library_version("src/lwpkt/libs/lwrb/library.json")
+    );
+
     let out_lwpkt = out_path.join("lwpkt");
     let out_lwrb = out_path.join("lwrb");
 
@@ -106,6 +231,7 @@ fn main() {
 
     let lwpk_c = out_path.join("lwpkt.c");
     std::fs::copy("src/lwpkt/lwpkt/src/lwpkt/lwpkt.c", &lwpk_c).unwrap();
+    LwPktOptions::patch_source(&lwpk_c);
 
     let lwrb_h = out_lwrb.join("lwrb.h");
     std::fs::copy("src/lwpkt/libs/lwrb/src/include/lwrb/lwrb.h", &lwrb_h).unwrap();
@@ -139,6 +265,11 @@ fn main() {
     let mut builder = cc::Build::new();
     builder.files(c_source);
     builder.include(&out_path);
+    // The bindings are always generated without atomics, bindgen cannot express them.
+    // `atomic_ulong` has the layout of `unsigned long` so the struct stays the same.
+    if std::env::var_os("CARGO_FEATURE_ATOMIC").is_none() {
+        builder.define("LWRB_DISABLE_ATOMIC", None);
+    }
     builder.compile("lwpkt");
 
     bindings
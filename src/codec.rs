@@ -0,0 +1,72 @@
+use bytes::BytesMut;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{Error, LwPkt, Package};
+
+/// `tokio_util::codec` adapter, the framing is done by the wrapped [`LwPkt`] without going
+/// through its raw channels
+pub struct LwPktCodec {
+    lwpkt: LwPkt,
+    // Decode error held back until the packets decoded along with it are handed out
+    error: Option<Error>,
+}
+
+impl LwPktCodec {
+    pub fn new(lwpkt: LwPkt) -> Self {
+        Self { lwpkt, error: None }
+    }
+
+    pub fn with_sizes(read_size: usize, write_size: usize) -> Result<Self, Error> {
+        let (lwpkt, _raw) = LwPkt::with_sizes(read_size, write_size)?;
+
+        Ok(Self::new(lwpkt))
+    }
+
+    pub fn get_ref(&self) -> &LwPkt {
+        &self.lwpkt
+    }
+
+    pub fn get_mut(&mut self) -> &mut LwPkt {
+        &mut self.lwpkt
+    }
+
+    pub fn into_inner(self) -> LwPkt {
+        self.lwpkt
+    }
+}
+
+impl Decoder for LwPktCodec {
+    type Item = Package;
+    type Error = std::io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Everything is handed to the decoder at once, partial frames stay in its ring buffer.
+        // A bad frame does not stop the rest of the chunk from being decoded.
+        if !src.is_empty() {
+            let bytes = src.split();
+            if let Err(e) = self.lwpkt.feed(&bytes) {
+                self.error.get_or_insert(e);
+            }
+        }
+
+        if let Some(package) = self.lwpkt.pending.pop_front() {
+            return Ok(Some(package));
+        }
+
+        match self.error.take() {
+            Some(e) => Err(e.into()),
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Package> for LwPktCodec {
+    type Error = std::io::Error;
+
+    fn encode(&mut self, item: Package, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let frame = self.lwpkt.encode(&item)?;
+        dst.extend_from_slice(&frame);
+
+        Ok(())
+    }
+}
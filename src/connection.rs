@@ -0,0 +1,112 @@
+use std::io::{Read, Write};
+
+use crate::{Error, LwPkt, LwRb, Package};
+
+/// Bytes taken from the transport by a single [`Connection::poll`]
+const READ_SIZE: usize = 1024;
+
+/// [`LwPkt`] bound to a blocking or non-blocking byte transport (serial port, TCP stream..),
+/// bytes are pumped by [`Connection::poll`] instead of going through [`crate::LwPktRaw`]
+pub struct Connection<T: Read + Write> {
+    lwpkt: LwPkt,
+    transport: T,
+    pre_tx: Option<TxHook>,
+    post_tx: Option<TxHook>,
+}
+
+type TxHook = Box<dyn FnMut() + Send>;
+
+impl<T: Read + Write> Connection<T> {
+    pub fn new(lwpkt: LwPkt, transport: T) -> Self {
+        Self {
+            lwpkt,
+            transport,
+            pre_tx: None,
+            post_tx: None,
+        }
+    }
+
+    pub fn with_sizes(transport: T, read_size: usize, write_size: usize) -> Result<Self, Error> {
+        let write_buffer = LwRb::new(write_size);
+        crate::check_write_buffer(&write_buffer)?;
+        let lwpkt = LwPkt::detached(LwRb::new(read_size), write_buffer)?;
+
+        Ok(Self::new(lwpkt, transport))
+    }
+
+    /// Frames `package` and writes it to the transport, returns once the transport is
+    /// flushed. Blocks while a blocking transport has no room.
+    pub fn send(&mut self, package: Package) -> Result<(), Error> {
+        // Framed first, a packet failing to frame never toggles the hooks
+        let frame = self.lwpkt.stage(&package)?;
+
+        if let Some(pre_tx) = self.pre_tx.as_mut() {
+            pre_tx();
+        }
+
+        let res = self
+            .transport
+            .write_all(frame)
+            .and_then(|_| self.transport.flush());
+
+        if let Some(post_tx) = self.post_tx.as_mut() {
+            post_tx();
+        }
+
+        Ok(res?)
+    }
+
+    /// Called by [`Connection::send`] right before the first byte of a frame is written,
+    /// e.g. to enable an RS-485 driver
+    pub fn set_pre_tx<F: FnMut() + Send + 'static>(&mut self, pre_tx: F) {
+        self.pre_tx = Some(Box::new(pre_tx));
+    }
+
+    /// Called by [`Connection::send`] once the transport is flushed, even when writing
+    /// failed. The turnaround is only right if `flush` waits for the last byte to leave.
+    pub fn set_post_tx<F: FnMut() + Send + 'static>(&mut self, post_tx: F) {
+        self.post_tx = Some(Box::new(post_tx));
+    }
+
+    /// Next packet decoded by [`Connection::poll`]
+    pub fn recv(&mut self) -> Option<Package> {
+        self.lwpkt.pending.pop_front()
+    }
+
+    /// Single read from the transport into the decoder, returns the number of packets ready
+    /// for [`Connection::recv`]
+    pub fn poll(&mut self) -> Result<usize, Error> {
+        let mut buffer = [0u8; READ_SIZE];
+
+        let len = match self.transport.read(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        self.lwpkt.feed(&buffer[..len])?;
+
+        Ok(self.lwpkt.pending.len())
+    }
+
+    pub fn get_ref(&self) -> &LwPkt {
+        &self.lwpkt
+    }
+
+    pub fn get_mut(&mut self) -> &mut LwPkt {
+        &mut self.lwpkt
+    }
+
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn into_inner(self) -> (LwPkt, T) {
+        (self.lwpkt, self.transport)
+    }
+}
@@ -1,19 +1,110 @@
+use std::collections::VecDeque;
 use std::pin::Pin;
+use std::time::Instant;
 
+#[cfg(feature = "async")]
 use async_channel::{Receiver, Sender};
 
-mod ffi;
+#[cfg(feature = "tokio-codec")]
+mod codec;
+mod connection;
+pub mod ffi;
+mod pool;
+#[cfg(feature = "tokio")]
+mod pump;
+mod reassembly;
+#[cfg(feature = "reliable")]
+mod reliable;
+mod sequence;
+#[cfg(feature = "futures")]
+mod sink;
+mod split;
+#[cfg(any(feature = "sync", not(feature = "async")))]
+mod sync;
+mod transport;
 
+#[cfg(feature = "tokio-codec")]
+pub use codec::LwPktCodec;
+pub use connection::Connection;
+pub use pool::{PacketPool, PooledPackage};
+#[cfg(feature = "tokio")]
+pub use pump::spawn_pump;
+pub use reassembly::Reassembler;
+#[cfg(feature = "reliable")]
+pub use reliable::{AckHandle, ReliableLink};
+pub use sequence::{SequenceEvent, SequenceTracker};
+#[cfg(feature = "futures")]
+pub use sink::PacketSink;
+pub use split::{RbConsumer, RbProducer};
+#[cfg(any(feature = "sync", not(feature = "async")))]
+pub use sync::{SyncRaw, SyncTransport};
+#[cfg(feature = "async")]
+pub use transport::ChannelTransport;
+pub use transport::RawTransport;
+
+enum Storage {
+    Owned(Box<[u8]>),
+    Borrowed(&'static mut [u8]),
+}
+
+impl Storage {
+    fn as_mut_ptr(&mut self) -> *mut u8 {
+        match self {
+            Storage::Owned(buffer) => buffer.as_mut_ptr(),
+            Storage::Borrowed(buffer) => buffer.as_mut_ptr(),
+        }
+    }
+
+    fn as_ptr(&self) -> *const u8 {
+        match self {
+            Storage::Owned(buffer) => buffer.as_ptr(),
+            Storage::Borrowed(buffer) => buffer.as_ptr(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Storage::Owned(buffer) => buffer.len(),
+            Storage::Borrowed(buffer) => buffer.len(),
+        }
+    }
+}
+
+/// The C ring buffer only points into the heap or `'static` memory, which stays put when
+/// the `LwRb` moves. The `lwrb` struct itself does move, which is why [`LwPkt`] pins its
+/// buffers before handing their address to lwpkt.
 pub struct LwRb {
     lwrb: ffi::lwrb,
-    buffer: Pin<Vec<u8>>,
+    buffer: Storage,
 }
 
+// Nothing in the `lwrb` struct is tied to the thread that created it
+unsafe impl Send for LwRb {}
+
 impl LwRb {
     pub fn new(size: usize) -> Self {
-        let mut lwrb = ffi::lwrb::default();
+        Self::init(Storage::Owned(vec![0u8; size].into_boxed_slice()))
+    }
 
-        let mut buffer = Pin::new(vec![0u8; size]);
+    /// Ring buffer holding exactly `n` bytes, one byte of a ring buffer always stays free so
+    /// `n + 1` are allocated
+    pub fn with_usable_capacity(n: usize) -> Self {
+        Self::new(n + 1)
+    }
+
+    /// Ring buffer over memory owned by the caller, e.g. both halves of one leaked arena:
+    ///
+    /// ```ignore
+    /// let arena: &'static mut [u8] = Box::leak(vec![0u8; 2048].into_boxed_slice());
+    /// let (read, write) = arena.split_at_mut(1024);
+    /// let (read, write) = (LwRb::from_slice(read), LwRb::from_slice(write));
+    /// ```
+    pub fn from_slice(buf: &'static mut [u8]) -> Self {
+        Self::init(Storage::Borrowed(buf))
+    }
+
+    fn init(mut buffer: Storage) -> Self {
+        let mut lwrb = ffi::lwrb::default();
 
         let res = unsafe {
             ffi::lwrb_init(
@@ -31,11 +122,75 @@ impl LwRb {
     pub fn size(&self) -> usize {
         self.buffer.len()
     }
+
+    /// Bytes the buffer can hold at once, one less than [`LwRb::size`]
+    pub fn capacity(&self) -> usize {
+        self.size().saturating_sub(1)
+    }
+
+    /// Bytes written and not read yet
+    pub fn used(&self) -> usize {
+        unsafe { ffi::lwrb_get_full(&self.lwrb as *const _) }
+    }
+
+    fn overlaps(&self, other: &LwRb) -> bool {
+        let start = self.buffer.as_ptr() as usize;
+        let other_start = other.buffer.as_ptr() as usize;
+
+        start < other_start + other.size() && other_start < start + self.size()
+    }
+
+    /// Returns how many bytes fit
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        unsafe {
+            ffi::lwrb_write(
+                &mut self.lwrb as *mut _,
+                data.as_ptr() as *mut _,
+                data.len(),
+            )
+        }
+    }
+
+    pub fn write_str(&mut self, s: &str) -> usize {
+        self.write(s.as_bytes())
+    }
+
+    /// Writes all of `data`, dropping the oldest unread bytes when there is no room instead
+    /// of applying backpressure. Only the last `size() - 1` bytes are kept when `data` is
+    /// larger than the buffer.
+    pub fn write_overwrite(&mut self, data: &[u8]) {
+        unsafe {
+            ffi::lwrb_overwrite(
+                &mut self.lwrb as *mut _,
+                data.as_ptr() as *mut _,
+                data.len(),
+            )
+        };
+    }
+
+    /// Offset from the read position of the first occurrence of `needle` at or after
+    /// `start`, without reading anything out
+    pub fn find(&self, needle: &[u8], start: usize) -> Option<usize> {
+        let mut found = 0;
+
+        let res = unsafe {
+            ffi::lwrb_find(
+                &self.lwrb as *const _,
+                needle.as_ptr() as *const _,
+                needle.len(),
+                start,
+                &mut found as *mut _,
+            )
+        };
+
+        (res != 0).then_some(found)
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[must_use]
 pub enum Error {
-    ERR = 0x1,
+    ERR,
     InProgress,
     Valid,
     ErrorCRC,
@@ -43,11 +198,90 @@ pub enum Error {
     WaitData,
     ErrorMem,
     ErrorClosedRaw,
+    // Rust side only, never produced from a `lwpktr_t`
+    PayloadTooLarge {
+        len: usize,
+        max: usize,
+    },
+    CmdOutOfRange {
+        cmd: u32,
+        max: u32,
+    },
+    Io {
+        kind: std::io::ErrorKind,
+        message: String,
+    },
+    AckTimeout {
+        seq: u8,
+    },
+    // Re-encoding a decoded frame gave different bytes, see `verify_frame`
+    FrameMismatch {
+        offset: usize,
+        encoded: Vec<u8>,
+    },
+    // Write buffer cannot hold a frame of `max_frame_len` bytes
+    BufferTooSmall {
+        size: usize,
+        min: usize,
+    },
+    // Package sent with a `from` other than the local address, see `LwPkt::set_strict_from`
+    FromMismatch {
+        from: u8,
+        address: u8,
+    },
+    // Application packet sent with the `ack_cmd` of a `ReliableLink`
+    AckCmdReserved {
+        cmd: u32,
+    },
+    // Every sequence number of a `ReliableLink` is waiting for its ACK
+    SeqInFlight {
+        seq: u8,
+    },
+    // `lwpktr_t` value this crate does not know about
+    Unknown(u32),
 }
 
-impl From<ffi::lwpktr_t::Type> for Error {
-    fn from(value: ffi::lwpktr_t::Type) -> Self {
-        match value {
+impl Error {
+    /// One value of every variant, in declaration order. Variants carrying data hold zeroed
+    /// or empty placeholders.
+    pub fn all() -> &'static [Error] {
+        static ALL: [Error; 18] = [
+            Error::ERR,
+            Error::InProgress,
+            Error::Valid,
+            Error::ErrorCRC,
+            Error::ErrStop,
+            Error::WaitData,
+            Error::ErrorMem,
+            Error::ErrorClosedRaw,
+            Error::PayloadTooLarge { len: 0, max: 0 },
+            Error::CmdOutOfRange { cmd: 0, max: 0 },
+            Error::Io {
+                kind: std::io::ErrorKind::Other,
+                message: String::new(),
+            },
+            Error::AckTimeout { seq: 0 },
+            Error::FrameMismatch {
+                offset: 0,
+                encoded: Vec::new(),
+            },
+            Error::BufferTooSmall { size: 0, min: 0 },
+            Error::FromMismatch {
+                from: 0,
+                address: 0,
+            },
+            Error::AckCmdReserved { cmd: 0 },
+            Error::SeqInFlight { seq: 0 },
+            Error::Unknown(0),
+        ];
+
+        &ALL
+    }
+
+    /// `None` for `lwpktOK`, never panics on a value added by a newer lwpkt
+    pub fn try_from_code(code: u32) -> Option<Error> {
+        let e = match code {
+            ffi::lwpktr_t::lwpktOK => return None,
             ffi::lwpktr_t::lwpktERR => Self::ERR,
             ffi::lwpktr_t::lwpktINPROG => Self::InProgress,
             ffi::lwpktr_t::lwpktVALID => Self::Valid,
@@ -55,316 +289,4856 @@ impl From<ffi::lwpktr_t::Type> for Error {
             ffi::lwpktr_t::lwpktERRSTOP => Self::ErrStop,
             ffi::lwpktr_t::lwpktWAITDATA => Self::WaitData,
             ffi::lwpktr_t::lwpktERRMEM => Self::ErrorMem,
-            e => {
-                panic!("Unknow type error: {e}")
+            code => Self::Unknown(code),
+        };
+
+        Some(e)
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ERR => write!(f, "lwpkt error"),
+            Self::InProgress => write!(f, "packet in progress"),
+            Self::Valid => write!(f, "packet valid"),
+            Self::ErrorCRC => write!(f, "CRC mismatch"),
+            Self::ErrStop => write!(f, "missing stop byte"),
+            Self::WaitData => write!(f, "waiting for data"),
+            Self::ErrorMem => write!(f, "not enough memory in the buffer"),
+            Self::ErrorClosedRaw => write!(f, "raw channel closed"),
+            Self::PayloadTooLarge { len, max } => {
+                write!(f, "payload of {len} bytes exceeds the max of {max}")
+            }
+            Self::CmdOutOfRange { cmd, max } => {
+                write!(f, "command {cmd:#x} exceeds the max of {max:#x}")
             }
+            Self::Io { kind, message } => write!(f, "io error ({kind}): {message}"),
+            Self::AckTimeout { seq } => write!(f, "no ACK for sequence {seq}"),
+            Self::FrameMismatch { offset, encoded } => write!(
+                f,
+                "re-encoded frame differs at byte {offset}, expected {encoded:02X?}"
+            ),
+            Self::BufferTooSmall { size, min } => {
+                write!(f, "buffer of {size} bytes is smaller than the min of {min}")
+            }
+            Self::FromMismatch { from, address } => write!(
+                f,
+                "packet from {from:#04x} sent by the node at address {address:#04x}"
+            ),
+            Self::AckCmdReserved { cmd } => write!(f, "command {cmd:#x} is reserved for ACKs"),
+            Self::SeqInFlight { seq } => write!(f, "sequence {seq} is still waiting for its ACK"),
+            Self::Unknown(code) => write!(f, "unknown lwpkt status {code}"),
         }
     }
 }
 
-fn check_result(res: u32) -> Result<(), Error> {
-    if res == ffi::lwpktr_t::lwpktOK {
-        Ok(())
-    } else {
-        Err(res.into())
+impl std::error::Error for Error {}
+
+/// A broken pipe means the other end is gone, like a closed raw channel
+impl From<std::io::Error> for Error {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            std::io::ErrorKind::BrokenPipe => Self::ErrorClosedRaw,
+            kind => Self::Io {
+                kind,
+                message: value.to_string(),
+            },
+        }
     }
 }
 
-pub struct LwPkt {
-    lwpkt: Pin<Box<ffi::lwpkt>>,
-    read_buffer: Pin<Box<LwRb>>,
-    write_buffer: Pin<Box<LwRb>>,
+impl From<Error> for std::io::Error {
+    fn from(value: Error) -> Self {
+        let kind = match &value {
+            Error::ErrorClosedRaw => std::io::ErrorKind::BrokenPipe,
+            Error::ErrorMem => std::io::ErrorKind::OutOfMemory,
+            // Back to what it was converted from
+            Error::Io { kind, message } => return std::io::Error::new(*kind, message.clone()),
+            _ => std::io::ErrorKind::InvalidData,
+        };
 
-    to_raw: Sender<Vec<u8>>,
-    from_raw: Receiver<Vec<u8>>,
+        std::io::Error::new(kind, value.to_string())
+    }
 }
 
-pub struct LwPktRaw {
-    last_read: Vec<u8>,
-    to_pkt: Sender<Vec<u8>>,
-    from_pkt: Receiver<Vec<u8>>,
+impl From<ffi::lwpktr_t::Type> for Error {
+    /// `lwpktOK` is not an error and comes out as [`Error::Unknown`]
+    fn from(value: ffi::lwpktr_t::Type) -> Self {
+        Self::try_from_code(value).unwrap_or(Self::Unknown(value))
+    }
 }
 
-#[derive(Debug, PartialEq, Eq)]
-#[allow(dead_code)]
-pub struct Package {
-    pub cmd: u32,
-    pub from: u8,
-    pub to: u8,
-    pub data: Vec<u8>,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    pub max_data_len: usize,
+    /// `false` only with the `no-crc` feature, the frames then carry no CRC byte and do
+    /// not interoperate with peers expecting one
+    pub use_crc: bool,
+    pub use_flags: bool,
+    pub use_addr: bool,
+    pub addr_extended: bool,
+    pub start_byte: u8,
+    pub stop_byte: u8,
 }
 
-impl LwPkt {
-    pub const MAX_PACKAGE_SIZE: u32 = ffi::LWPKT_CFG_MAX_DATA_LEN;
+/// Versions of the lwpkt and lwrb sources compiled in, `"unknown"` when their
+/// `library.json` is missing
+pub fn library_version() -> (&'static str, &'static str) {
+    (env!("LWPKT_LIB_VERSION"), env!("LWRB_LIB_VERSION"))
+}
 
-    pub fn new(read_buffer: LwRb, write_buffer: LwRb) -> Result<(Self, LwPktRaw), Error> {
-        let lwpkt = Box::pin(ffi::lwpkt::default());
+/// Options the bundled lwpkt was compiled with
+pub fn config() -> Config {
+    Config {
+        max_data_len: ffi::LWPKT_CFG_MAX_DATA_LEN as usize,
+        use_crc: ffi::LWPKT_CFG_USE_CRC != 0,
+        use_flags: ffi::LWPKT_CFG_USE_FLAGS != 0,
+        use_addr: ffi::LWPKT_CFG_USE_ADDR != 0,
+        addr_extended: ffi::LWPKT_CFG_ADDR_EXTENDED != 0,
+        start_byte: ffi::LWPKT_CFG_START_BYTE as u8,
+        stop_byte: ffi::LWPKT_CFG_STOP_BYTE as u8,
+    }
+}
 
-        let (tx_to_raw, rx_to_raw) = async_channel::bounded(64);
-        let (tx_to_pkt, rx_to_pkt) = async_channel::bounded(64);
+/// Destination every node accepts, lwpkt's default `LWPKT_CFG_ADDR_BROADCAST`
+pub const BROADCAST_ADDRESS: u8 = 0xFF;
 
-        let mut result = Self {
-            lwpkt,
-            read_buffer: Box::pin(read_buffer),
-            write_buffer: Box::pin(write_buffer),
-            to_raw: tx_to_raw,
-            from_raw: rx_to_pkt,
-        };
+/// Wire length of the largest frame the compiled configuration can produce, extended
+/// addresses and flags counted at their widest varint
+pub fn max_frame_len() -> usize {
+    frame_len(config().max_data_len)
+}
 
-        let res = unsafe {
-            ffi::lwpkt_init(
-                result.lwpkt.as_mut().get_mut() as *mut _,
-                &mut result.write_buffer.lwrb as *mut _,
-                &mut result.read_buffer.lwrb as *mut _,
-            )
-        };
-        check_result(res)?;
+// Wire length of a frame carrying `data_len` bytes, see `max_frame_len`
+fn frame_len(data_len: usize) -> usize {
+    let config = config();
+    let varint = |bits: u32| bits.div_ceil(7) as usize;
 
-        let raw = LwPktRaw {
-            last_read: Vec::new(),
-            to_pkt: tx_to_pkt,
-            from_pkt: rx_to_raw,
-        };
+    let addr = match (config.use_addr, config.addr_extended) {
+        (false, _) => 0,
+        (true, false) => 2,
+        (true, true) => 2 * varint(32),
+    };
+    let flags = if config.use_flags { varint(32) } else { 0 };
+    let cmd = std::mem::size_of_val(&ffi::lwpkt::default().m.cmd);
+    let len = varint(usize::BITS - data_len.leading_zeros()).max(1);
 
-        Ok((result, raw))
-    }
+    // Start and stop bytes
+    2 + addr + flags + cmd + len + data_len + usize::from(config.use_crc)
+}
 
-    pub fn set_addres(&mut self, address: u8) -> Result<(), Error> {
-        let res = unsafe { ffi::lwpkt_set_addr(self.lwpkt.as_mut().get_mut() as *mut _, address) };
+/// CRC-8 (Dallas/Maxim) lwpkt appends to every frame, computed over everything between the
+/// start byte and the CRC itself
+#[cfg(not(feature = "no-crc"))]
+pub fn crc(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
 
-        check_result(res)
+    for &byte in data {
+        let mut byte = byte;
+        for _ in 0..8 {
+            let mix = (crc ^ byte) & 0x01;
+            crc >>= 1;
+            if mix != 0 {
+                crc ^= 0x8C;
+            }
+            byte >>= 1;
+        }
     }
 
-    pub fn write(&mut self, package: Package) -> Result<(), Error> {
-        let res = unsafe {
-            ffi::lwpkt_write(
-                self.lwpkt.as_mut().get_mut() as *mut _,
-                package.to,
-                package.cmd as _,
-                package.data.as_ptr() as *mut _,
-                package.data.len(),
-            )
-        };
-
-        check_result(res)?;
+    crc
+}
 
-        let wb = &mut self.write_buffer.lwrb as *mut _;
+// A ring buffer holds one byte less than its size
+pub(crate) fn check_write_buffer(write_buffer: &LwRb) -> Result<(), Error> {
+    let min = max_frame_len() + 1;
+    if write_buffer.size() < min {
+        return Err(Error::BufferTooSmall {
+            size: write_buffer.size(),
+            min,
+        });
+    }
 
-        let mut buffer = vec![0u8; 1024];
-        loop {
-            let res = unsafe { ffi::lwrb_read(wb, buffer.as_mut_ptr() as *mut _, buffer.len()) };
+    Ok(())
+}
 
-            if res == 0 {
-                break;
-            }
+/// Decodes `bytes` on a fresh context and returns every packet and decode error in order.
+/// Arbitrary input only ever yields [`Error`] values, which makes it a fuzzing target.
+pub fn decode_all(bytes: &[u8]) -> Vec<Result<Package, Error>> {
+    let mut lwpkt = match LwPkt::detached(LwRb::new(1024), LwRb::new(16)) {
+        Ok(lwpkt) => lwpkt,
+        Err(e) => return vec![Err(e)],
+    };
 
-            match self.to_raw.try_send((&buffer[..res]).to_vec()) {
-                Ok(_) => {}
-                Err(async_channel::TrySendError::Full(_)) => {
-                    return Err(Error::ErrorMem);
-                }
-                Err(async_channel::TrySendError::Closed(_)) => {
-                    return Err(Error::ErrorClosedRaw);
-                }
-            }
-        }
+    let mut results = Vec::new();
 
-        Ok(())
-    }
+    let mut from = 0;
+    while from < bytes.len() {
+        from += lwpkt.write_read_buffer(&bytes[from..]);
 
-    pub fn read(&'_ mut self) -> Result<Vec<Package>, Error> {
-        let mut results = Vec::new();
+        // Drain the read buffer completely so the next write has room
         loop {
-            match self.from_raw.try_recv() {
-                Ok(buffer) => {
-                    let mut from = 0;
-                    while from < buffer.len() {
-                        let res = unsafe {
-                            ffi::lwrb_write(
-                                &mut self.read_buffer.lwrb as *mut _,
-                                (&buffer[from..]).as_ptr() as *mut _,
-                                buffer.len() - from,
-                            )
-                        };
-
-                        let status =
-                            unsafe { ffi::lwpkt_read(self.lwpkt.as_mut().get_mut() as *mut _) };
-
-                        match status {
-                            ffi::lwpktr_t::lwpktVALID => {
-                                results.push(Package {
-                                    cmd: self.get_cmd(),
-                                    data: self.get_data().to_vec(),
-                                    from: self.get_from(),
-                                    to: self.get_to(),
-                                });
-                            }
-                            ffi::lwpktr_t::lwpktWAITDATA => {}
-                            ffi::lwpktr_t::lwpktINPROG => {}
-                            e => return Err(e.into()),
-                        };
-
-                        from += res;
-                    }
-                }
-                Err(async_channel::TryRecvError::Empty) => {
-                    break;
-                }
-                Err(_) => {
-                    return Err(Error::ErrorClosedRaw);
-                }
+            match lwpkt.decode_step() {
+                Ok(Some(package)) => results.push(Ok(package)),
+                Ok(None) => break,
+                Err(e) => results.push(Err(e)),
             }
         }
-
-        Ok(results)
     }
 
-    pub fn get_data(&self) -> &[u8] {
-        let len = self.lwpkt.m.len;
-        &self.lwpkt.data[..len]
-    }
+    results
+}
 
-    pub fn get_cmd(&self) -> u32 {
-        self.lwpkt.m.cmd as u32
-    }
+/// Decodes the single frame in `bytes` and encodes the packet again, the result must match
+/// `bytes` exactly. Catches frames this build decodes but would never produce, such as a
+/// length with extra varint groups.
+pub fn verify_frame(bytes: &[u8]) -> Result<Package, Error> {
+    let package = decode_all(bytes)
+        .into_iter()
+        .next()
+        .unwrap_or(Err(Error::WaitData))?;
 
-    fn get_from(&self) -> u8 {
-        self.lwpkt.m.from
-    }
+    let mut lwpkt = LwPkt::detached(LwRb::new(16), LwRb::new(1024))?;
+    #[cfg(not(feature = "no-addr"))]
+    lwpkt.set_addres(package.from)?;
 
-    fn get_to(&self) -> u8 {
-        self.lwpkt.m.to
+    let encoded = lwpkt.encode(&package)?;
+    if encoded != bytes {
+        let offset = encoded
+            .iter()
+            .zip(bytes)
+            .position(|(a, b)| a != b)
+            .unwrap_or(encoded.len().min(bytes.len()));
+
+        return Err(Error::FrameMismatch { offset, encoded });
     }
 
-    #[allow(dead_code)]
-    fn raw_write(&mut self, raw: &[u8]) -> Result<(), Error> {
-        let _res = unsafe {
-            ffi::lwrb_write(
-                &mut self.write_buffer.lwrb as *mut _,
-                raw.as_ptr() as *mut _,
-                raw.len(),
-            )
-        };
+    Ok(package)
+}
 
-        todo!()
+fn check_result(res: u32) -> Result<(), Error> {
+    match Error::try_from_code(res) {
+        None => Ok(()),
+        Some(e) => Err(e),
     }
 }
 
-impl std::io::Read for LwPktRaw {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let mut readed = 0usize;
-        if !self.last_read.is_empty() {
-            match buf.len().cmp(&self.last_read.len()) {
-                std::cmp::Ordering::Less => {
-                    buf.copy_from_slice(&self.last_read[..buf.len()]);
-                    self.last_read = self.last_read[buf.len()..].to_vec();
-                    return Ok(buf.len());
-                }
-                std::cmp::Ordering::Equal => {
-                    buf.copy_from_slice(&self.last_read);
-                    self.last_read = Vec::new();
-                    return Ok(buf.len());
-                }
-                std::cmp::Ordering::Greater => {
-                    (&mut buf[..self.last_read.len()]).copy_from_slice(&self.last_read);
-                    readed = self.last_read.len();
-                    self.last_read = Vec::new();
-                }
-            }
-        }
+// Decoder context, allocated here or adopted from C by `LwPkt::from_raw_parts`
+enum Context {
+    Owned(Pin<Box<ffi::lwpkt>>),
+    // `from_raw_parts` hands out an `LwPktRaw`, it only exists with the `async` feature
+    #[cfg_attr(not(feature = "async"), allow(dead_code))]
+    Borrowed(*mut ffi::lwpkt),
+}
 
-        loop {
-            match self.from_pkt.try_recv() {
-                Ok(src) => {
-                    let buffer = &mut buf[readed..];
-                    match buffer.len().cmp(&src.len()) {
-                        std::cmp::Ordering::Less => {
-                            buffer.copy_from_slice(&src[..buffer.len()]);
-                            self.last_read = src[buffer.len()..].to_vec();
-                            readed += buffer.len();
-                            return Ok(readed);
-                        }
-                        std::cmp::Ordering::Equal => {
-                            buffer.copy_from_slice(&src);
-                            readed += buffer.len();
-                            return Ok(readed);
-                        }
-                        std::cmp::Ordering::Greater => {
-                            (&mut buffer[..src.len()]).copy_from_slice(&src);
-                            readed += src.len();
-                        }
-                    }
-                }
-                Err(async_channel::TryRecvError::Empty) => break,
-                Err(e) => {
-                    return Err(std::io::Error::new(
-                        std::io::ErrorKind::BrokenPipe,
-                        e.to_string(),
-                    ));
-                }
-            }
+impl Context {
+    fn as_mut_ptr(&mut self) -> *mut ffi::lwpkt {
+        match self {
+            Context::Owned(lwpkt) => lwpkt.as_mut().get_mut() as *mut _,
+            Context::Borrowed(lwpkt) => *lwpkt,
         }
-
-        Ok(readed)
     }
 }
 
-impl std::io::Write for LwPktRaw {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self.to_pkt.try_send(buf.to_vec()) {
-            Ok(()) => Ok(buf.len()),
-            Err(async_channel::TrySendError::Full(_)) => Ok(0),
-            Err(async_channel::TrySendError::Closed(v)) => Err(std::io::Error::new(
-                std::io::ErrorKind::BrokenPipe,
-                async_channel::TrySendError::Closed(v).to_string(),
-            )),
+impl std::ops::Deref for Context {
+    type Target = ffi::lwpkt;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Context::Owned(lwpkt) => lwpkt,
+            Context::Borrowed(lwpkt) => unsafe { &**lwpkt },
         }
     }
+}
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        Ok(())
-    }
+/// Transport of an [`LwPkt`] when none is named: the [`LwPktRaw`] channels with the `async`
+/// feature (on by default), [`SyncTransport`] without it
+#[cfg(feature = "async")]
+pub type DefaultTransport = ChannelTransport;
+#[cfg(not(feature = "async"))]
+pub type DefaultTransport = SyncTransport;
+
+/// A single owner drives the decoder, there is no locking on the Rust or the C side. It can
+/// be moved to another thread and shared behind a `Mutex`, lwpkt offers no thread-safe mode
+/// that would let it be used concurrently.
+pub struct LwPkt<T: RawTransport = DefaultTransport> {
+    lwpkt: Context,
+    // Ring buffers `lwpkt` points to, `None` when they belong to an adopted C context
+    _buffers: Option<Buffers>,
+
+    transport: T,
+
+    valid: bool,
+    // Last `lwpkt_read` result other than WAITDATA
+    last_status: ffi::lwpktr_t::Type,
+    // Bytes of the frame being decoded, and of the last one failing its CRC
+    window: Vec<u8>,
+    bad_frame: Option<Vec<u8>>,
+    // Copy of the unread bytes in the read buffer, saves peeking the whole buffer before
+    // every `lwpkt_read`
+    unread: VecDeque<u8>,
+    // Reused by `stage`, grows to the largest frame written
+    staging: Vec<u8>,
+    // Payload buffers for decoded packets
+    pool: PacketPool,
+    // Runtime cap on outgoing payloads, at most `MAX_PACKAGE_SIZE`
+    max_data_len: usize,
+    // Decoded packets not handed out yet, every read-style method drains from here
+    pending: VecDeque<Package>,
+    // Cap on the packets a read decodes and returns, `None` for no cap
+    max_packets_per_read: Option<usize>,
+    #[cfg(not(feature = "no-addr"))]
+    filter_by_address: bool,
+    #[cfg(not(feature = "no-addr"))]
+    strict_from: bool,
+    // Packets dropped by `filter_by_address`
+    filtered: usize,
+    on_packet: Option<PacketCallback>,
+    decode_trace: Option<DecodeTrace>,
+    #[cfg(test)]
+    ffi_calls: FfiCalls,
 }
 
+// Ring buffer and decoder calls made while decoding, counted for the tests
 #[cfg(test)]
-mod test {
-    use std::io::{Read, Write};
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct FfiCalls {
+    lwrb_write: usize,
+    lwrb_peek: usize,
+    lwpkt_read: usize,
+}
 
-    use crate::{LwPkt, LwRb};
+// The context only points to the owned ring buffers, or to C memory `from_raw_parts` hands
+// over to this `LwPkt`
+unsafe impl<T: RawTransport + Send> Send for LwPkt<T> {}
 
-    #[test]
-    fn init_test() {
-        let rb = LwRb::new(1024);
-        let wb = LwRb::new(1024);
+type PacketCallback = Box<dyn FnMut(&Package) + Send>;
 
-        let (mut lwpkt, mut raw_pkt) = LwPkt::new(rb, wb).unwrap();
+type DecodeTrace = Box<dyn FnMut(DecodeEvent) + Send>;
 
-        lwpkt.set_addres(0x12).unwrap();
+// Read and write ring buffers
+type Buffers = (Pin<Box<LwRb>>, Pin<Box<LwRb>>);
 
-        lwpkt
-            .write(crate::Package {
-                cmd: 0x85,
-                from: 0,
-                to: 0x11,
-                data: b"some hello".to_vec(),
-            })
-            .unwrap();
+#[cfg(feature = "async")]
+pub struct LwPktRaw {
+    last_read: Vec<u8>,
+    to_pkt: Sender<Vec<u8>>,
+    from_pkt: Receiver<Vec<u8>>,
+    taps: Vec<Sender<Vec<u8>>>,
+    // Pool of the `LwPkt`, gets back the chunks read through `std::io::Read`
+    pool: PacketPool,
+}
 
-        let mut buffer = vec![];
-        raw_pkt.read_to_end(&mut buffer).unwrap();
+/// Result of [`LwPkt::try_write`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sent {
+    All,
+    /// The transport has no room for the frame. The raw channels take a frame as a single
+    /// chunk so nothing is queued yet, `remaining` can be retried with
+    /// [`LwPkt::try_write_frame`].
+    Partial {
+        queued: usize,
+        remaining: Vec<u8>,
+    },
+}
 
-        raw_pkt.write_all(&buffer).unwrap();
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadOutcome {
+    /// Nothing arrived from the raw side
+    Idle,
+    /// Bytes arrived but no packet is complete yet
+    Progressed,
+    Packets(Vec<Package>),
+}
 
-        let s = lwpkt.read().unwrap();
+/// Decoder step reported to [`LwPkt::set_decode_trace`]
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecodeEvent {
+    StartByte,
+    /// Length header parsed, payload length in bytes
+    Length(usize),
+    CrcMismatch {
+        expected: u8,
+        got: u8,
+    },
+    StopByteMismatch {
+        got: u8,
+    },
+    Packet {
+        cmd: u32,
+        len: usize,
+    },
+    /// Any other decode error
+    Error(Error),
+}
 
-        assert_eq!(
-            (*s.first().unwrap()),
+/// With the `no-addr` feature `from` and `to` are not on the wire, they are ignored on write
+/// and always `0` on read. The same goes for `flags` without the `flags` feature, struct
+/// literals still have to set it (`0` for none) while [`Package::builder`] leaves it at `0`.
+///
+/// The wire format does not depend on the host byte order: `cmd` and non extended addresses
+/// are single bytes, while the length (and extended addresses and flags when enabled) are
+/// base-128 varints, least significant group first, with the high bit marking continuation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(dead_code)]
+pub struct Package {
+    pub cmd: u32,
+    pub from: u8,
+    pub to: u8,
+    pub flags: u32,
+    pub data: Vec<u8>,
+}
+
+impl Package {
+    pub fn builder() -> PackageBuilder {
+        PackageBuilder::default()
+    }
+
+    pub fn data_eq(&self, other: &[u8]) -> bool {
+        self.data == other
+    }
+}
+
+/// Named setters for the fields of a [`Package`], unset ones are `0` or empty. `from` can be
+/// left out when writing, lwpkt puts the address of the sending [`LwPkt`] on the wire.
+#[derive(Debug, Clone, Default)]
+pub struct PackageBuilder {
+    cmd: u32,
+    from: u8,
+    to: u8,
+    flags: u32,
+    data: Vec<u8>,
+}
+
+impl PackageBuilder {
+    pub fn cmd(mut self, cmd: u32) -> Self {
+        self.cmd = cmd;
+        self
+    }
+
+    pub fn from(mut self, from: u8) -> Self {
+        self.from = from;
+        self
+    }
+
+    pub fn to(mut self, to: u8) -> Self {
+        self.to = to;
+        self
+    }
+
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn data(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.data = data.into();
+        self
+    }
+
+    pub fn build(self) -> Package {
+        Package {
+            cmd: self.cmd,
+            from: self.from,
+            to: self.to,
+            flags: self.flags,
+            data: self.data,
+        }
+    }
+}
+
+/// Random packets within the limits of the current build, for property tests
+#[cfg(feature = "testing")]
+impl proptest::arbitrary::Arbitrary for Package {
+    type Parameters = ();
+    type Strategy = proptest::strategy::BoxedStrategy<Self>;
+
+    fn arbitrary_with(_args: Self::Parameters) -> Self::Strategy {
+        use proptest::prelude::*;
+
+        (
+            0..=u8::MAX as u32,
+            any::<u8>(),
+            any::<u8>(),
+            any::<u32>(),
+            proptest::collection::vec(any::<u8>(), 0..=LwPkt::MAX_PACKAGE_SIZE as usize),
+        )
+            .prop_map(|(cmd, from, to, flags, data)| Package {
+                cmd,
+                from,
+                to,
+                flags: if cfg!(feature = "flags") { flags } else { 0 },
+                data,
+            })
+            .boxed()
+    }
+}
+
+#[cfg(feature = "async")]
+#[derive(Default)]
+pub struct LwPktBuilder {
+    read_buffer: Option<LwRb>,
+    write_buffer: Option<LwRb>,
+    #[cfg(not(feature = "no-addr"))]
+    address: Option<u8>,
+    on_packet: Option<PacketCallback>,
+    max_packets_per_read: Option<usize>,
+    #[cfg(not(feature = "no-addr"))]
+    filter_by_address: bool,
+    #[cfg(not(feature = "no-addr"))]
+    strict_from: bool,
+}
+
+#[cfg(feature = "async")]
+impl LwPktBuilder {
+    pub const DEFAULT_BUFFER_SIZE: usize = 1024;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_buffer(mut self, read_buffer: LwRb) -> Self {
+        self.read_buffer = Some(read_buffer);
+        self
+    }
+
+    pub fn write_buffer(mut self, write_buffer: LwRb) -> Self {
+        self.write_buffer = Some(write_buffer);
+        self
+    }
+
+    /// Set before the first packet can be written
+    #[cfg(not(feature = "no-addr"))]
+    pub fn address(mut self, address: u8) -> Self {
+        self.address = Some(address);
+        self
+    }
+
+    /// Installed before any byte is decoded, see [`LwPkt::set_on_packet`]
+    pub fn on_packet<F: FnMut(&Package) + Send + 'static>(mut self, on_packet: F) -> Self {
+        self.on_packet = Some(Box::new(on_packet));
+        self
+    }
+
+    /// See [`LwPkt::set_filter_by_address`]
+    #[cfg(not(feature = "no-addr"))]
+    pub fn filter_by_address(mut self, filter: bool) -> Self {
+        self.filter_by_address = filter;
+        self
+    }
+
+    /// See [`LwPkt::set_strict_from`]
+    #[cfg(not(feature = "no-addr"))]
+    pub fn strict_from(mut self, strict: bool) -> Self {
+        self.strict_from = strict;
+        self
+    }
+
+    /// See [`LwPkt::set_max_packets_per_read`]
+    pub fn max_packets_per_read(mut self, max: usize) -> Self {
+        self.max_packets_per_read = Some(max);
+        self
+    }
+
+    pub fn build(self) -> Result<(LwPkt, LwPktRaw), Error> {
+        let read_buffer = self
+            .read_buffer
+            .unwrap_or_else(|| LwRb::new(Self::DEFAULT_BUFFER_SIZE));
+        let write_buffer = self
+            .write_buffer
+            .unwrap_or_else(|| LwRb::new(Self::DEFAULT_BUFFER_SIZE));
+
+        let (mut lwpkt, raw) = LwPkt::new(read_buffer, write_buffer)?;
+        lwpkt.on_packet = self.on_packet;
+        lwpkt.max_packets_per_read = self.max_packets_per_read;
+
+        #[cfg(not(feature = "no-addr"))]
+        {
+            lwpkt.filter_by_address = self.filter_by_address;
+            lwpkt.strict_from = self.strict_from;
+        }
+
+        #[cfg(not(feature = "no-addr"))]
+        if let Some(address) = self.address {
+            lwpkt.set_addres(address)?;
+        }
+
+        Ok((lwpkt, raw))
+    }
+}
+
+impl LwPkt {
+    /// Set by `LWPKT_CFG_MAX_DATA_LEN` at build time. The length is a varint on the wire, no
+    /// other option is needed for payloads over 255 bytes.
+    pub const MAX_PACKAGE_SIZE: u32 = ffi::LWPKT_CFG_MAX_DATA_LEN;
+    /// Chunks each raw channel holds, see [`LwPkt::rebuild_channels`] to change it
+    pub const CHANNEL_CAPACITY: usize = 64;
+
+    // Encoder and decoder only, the other end of its transport is gone
+    pub(crate) fn detached(read_buffer: LwRb, write_buffer: LwRb) -> Result<Self, Error> {
+        #[cfg(feature = "async")]
+        return Self::with_raw(|transport| Self::init(read_buffer, write_buffer, transport))
+            .map(|(lwpkt, _raw)| lwpkt);
+
+        #[cfg(not(feature = "async"))]
+        Self::init(read_buffer, write_buffer, SyncTransport::pair().0)
+    }
+}
+
+#[cfg(feature = "async")]
+impl LwPkt {
+    /// The two ring buffers must be independent, bytes framed for transmission never reach
+    /// the decoder. Use [`LwPkt::new_loopback`] to decode your own packets.
+    ///
+    /// ```
+    /// use std::io::{Read, Write};
+    ///
+    /// use lwpkt_rs::{LwPkt, LwRb, Package};
+    ///
+    /// let (mut lwpkt, mut raw) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+    ///
+    /// let package = Package { cmd: 0x10, from: 0, to: 0, flags: 0, data: b"hello".to_vec() };
+    /// lwpkt.write(package.clone()).unwrap();
+    ///
+    /// // What goes on the wire, fed straight back as if the other end answered
+    /// let mut wire = Vec::new();
+    /// raw.read_to_end(&mut wire).unwrap();
+    /// raw.write_all(&wire).unwrap();
+    ///
+    /// assert_eq!(lwpkt.read().unwrap(), vec![package]);
+    /// ```
+    ///
+    /// A frame is written to the write buffer as a whole, which must hold [`max_frame_len`]
+    /// bytes or [`Error::BufferTooSmall`] is returned. The read buffer is decoded as bytes
+    /// arrive, any size works.
+    pub fn new(read_buffer: LwRb, write_buffer: LwRb) -> Result<(Self, LwPktRaw), Error> {
+        debug_assert!(
+            !read_buffer.overlaps(&write_buffer),
+            "read and write buffers share memory"
+        );
+
+        check_write_buffer(&write_buffer)?;
+
+        Self::with_raw(|transport| Self::init(read_buffer, write_buffer, transport))
+    }
+
+    /// Adopts a context set up on the C side, neither it nor its ring buffers are
+    /// initialized again and they are not freed on drop.
+    ///
+    /// # Safety
+    ///
+    /// `lwpkt` must point to a context initialized with `lwpkt_init`, whose ring buffers
+    /// have distinct memory. The context, the ring buffers and their memory must stay valid
+    /// and must not be touched by anything else for as long as the returned [`LwPkt`]
+    /// lives, from whichever thread it is moved to.
+    pub unsafe fn from_raw_parts(lwpkt: *mut ffi::lwpkt) -> Result<(Self, LwPktRaw), Error> {
+        Self::with_raw(|transport| {
+            let context = Context::Borrowed(lwpkt);
+            Ok(Self::with_context(context, None, transport))
+        })
+    }
+
+    fn with_raw(
+        init: impl FnOnce(ChannelTransport) -> Result<Self, Error>,
+    ) -> Result<(Self, LwPktRaw), Error> {
+        let (tx_to_raw, rx_to_raw) = async_channel::bounded(Self::CHANNEL_CAPACITY);
+        let (tx_to_pkt, rx_to_pkt) = async_channel::bounded(Self::CHANNEL_CAPACITY);
+
+        let result = init(ChannelTransport {
+            to_raw: tx_to_raw,
+            from_raw: rx_to_pkt,
+            outgoing: rx_to_raw.downgrade(),
+        })?;
+
+        let raw = LwPktRaw {
+            last_read: Vec::new(),
+            to_pkt: tx_to_pkt,
+            from_pkt: rx_to_raw,
+            taps: Vec::new(),
+            pool: result.pool.clone(),
+        };
+
+        Ok((result, raw))
+    }
+
+    /// Every written packet is handed back by the next read, there is no raw side. The write
+    /// buffer must hold [`max_frame_len`] bytes, as for [`LwPkt::new`].
+    pub fn new_loopback(read_buffer: LwRb, write_buffer: LwRb) -> Result<Self, Error> {
+        debug_assert!(
+            !read_buffer.overlaps(&write_buffer),
+            "read and write buffers share memory"
+        );
+
+        check_write_buffer(&write_buffer)?;
+
+        let (tx, rx) = async_channel::bounded(Self::CHANNEL_CAPACITY);
+
+        let outgoing = rx.downgrade();
+        let transport = ChannelTransport {
+            to_raw: tx,
+            from_raw: rx,
+            outgoing,
+        };
+
+        Self::init(read_buffer, write_buffer, transport)
+    }
+
+    pub fn builder() -> LwPktBuilder {
+        LwPktBuilder::new()
+    }
+
+    pub fn with_sizes(read_size: usize, write_size: usize) -> Result<(Self, LwPktRaw), Error> {
+        Self::new(LwRb::new(read_size), LwRb::new(write_size))
+    }
+
+    /// `false` once the [`LwPktRaw`] half is dropped, writes would fail with
+    /// [`Error::ErrorClosedRaw`]
+    pub fn is_raw_connected(&self) -> bool {
+        !self.transport.to_raw.is_closed()
+    }
+
+    /// Chunks that can still be queued for the [`LwPktRaw`] before writes fail with
+    /// [`Error::ErrorMem`], every written packet takes one
+    pub fn raw_channel_capacity_remaining(&self) -> usize {
+        let to_raw = &self.transport.to_raw;
+
+        to_raw.capacity().unwrap_or(0).saturating_sub(to_raw.len())
+    }
+
+    /// Closes both raw channels, a pending async read or write on the [`LwPktRaw`] side
+    /// wakes up with a closed error. Chunks not decoded yet are dropped, the ones queued for
+    /// the [`LwPktRaw`] can still be read by it.
+    pub fn close(&mut self) {
+        self.transport.to_raw.close();
+        self.transport.from_raw.close();
+
+        while self.transport.from_raw.try_recv().is_ok() {}
+    }
+
+    /// Drops the chunks queued in both raw channels, the decoder state is kept. Bytes the
+    /// [`LwPktRaw`] already took out of the channel are not affected.
+    pub fn drain_channels(&mut self) {
+        while self.transport.from_raw.try_recv().is_ok() {}
+
+        if let Some(outgoing) = self.transport.outgoing.upgrade() {
+            while outgoing.try_recv().is_ok() {}
+        }
+    }
+
+    /// Replaces both raw channels with ones holding `capacity` chunks and returns the new
+    /// [`LwPktRaw`]. Queued chunks move over, those not fitting are dropped. The previous
+    /// [`LwPktRaw`], its senders and its taps are disconnected, a loopback [`LwPkt`] stops
+    /// being one.
+    pub fn rebuild_channels(&mut self, capacity: usize) -> LwPktRaw {
+        let (tx_to_raw, rx_to_raw) = async_channel::bounded(capacity);
+        let (tx_to_pkt, rx_to_pkt) = async_channel::bounded(capacity);
+
+        let mut queued = vec![];
+        if let Some(outgoing) = self.transport.outgoing.upgrade() {
+            while let Ok(chunk) = outgoing.try_recv() {
+                queued.push((&tx_to_raw, chunk));
+            }
+        }
+        while let Ok(chunk) = self.transport.from_raw.try_recv() {
+            queued.push((&tx_to_pkt, chunk));
+        }
+
+        for (tx, chunk) in queued {
+            if tx.try_send(chunk).is_err() {
+                log::warn!("Chunk dropped while rebuilding the raw channels");
+            }
+        }
+
+        self.transport.to_raw = tx_to_raw;
+        self.transport.outgoing = rx_to_raw.downgrade();
+        self.transport.from_raw = rx_to_pkt;
+
+        LwPktRaw {
+            last_read: Vec::new(),
+            to_pkt: tx_to_pkt,
+            from_pkt: rx_to_raw,
+            taps: Vec::new(),
+            pool: self.pool.clone(),
+        }
+    }
+
+    /// Bytes queued for the [`LwPktRaw`] and not taken yet, left in place. The queue is
+    /// drained and refilled, so it must not be written to concurrently.
+    #[cfg(feature = "testing")]
+    pub fn peek_outgoing(&self) -> Vec<u8> {
+        let Some(outgoing) = self.transport.outgoing.upgrade() else {
+            return vec![];
+        };
+
+        let mut chunks = vec![];
+        while let Ok(chunk) = outgoing.try_recv() {
+            chunks.push(chunk);
+        }
+
+        let bytes = chunks.concat();
+        for chunk in chunks {
+            // Room was just made by taking the very same chunks out
+            let _ = self.transport.to_raw.try_send(chunk);
+        }
+
+        bytes
+    }
+
+    /// Frames `package` and hands it to the raw channel in one go, like [`LwPkt::write`] but
+    /// a full channel is awaited instead of failing with [`Error::ErrorMem`]. Never resolves
+    /// if the [`LwPktRaw`] half stops reading without being dropped.
+    pub async fn write_flush(&mut self, package: Package) -> Result<(), Error> {
+        let frame = self.encode(&package)?;
+
+        self.transport
+            .to_raw
+            .send(frame)
+            .await
+            .map_err(|_| Error::ErrorClosedRaw)
+    }
+
+    /// Waits for the next packet, packets decoded along with it are kept for the next calls
+    pub async fn recv_one(&mut self) -> Result<Package, Error> {
+        loop {
+            if let Some(package) = self.pending.pop_front() {
+                return Ok(package);
+            }
+
+            let buffer = self
+                .transport
+                .from_raw
+                .recv()
+                .await
+                .map_err(|_| Error::ErrorClosedRaw)?;
+
+            self.feed(&buffer)?;
+        }
+    }
+
+    /// Stream of incoming packets, decode errors are yielded and the stream goes on, it ends
+    /// once the [`LwPktRaw`] half is gone
+    #[cfg(feature = "futures")]
+    pub fn into_stream(self) -> impl futures_core::Stream<Item = Result<Package, Error>> {
+        futures_util::stream::unfold(self, |mut lwpkt| async move {
+            match lwpkt.recv_one().await {
+                Ok(package) => Some((Ok(package), lwpkt)),
+                Err(Error::ErrorClosedRaw) => None,
+                Err(e) => Some((Err(e), lwpkt)),
+            }
+        })
+    }
+
+    /// Sink of outgoing packets, backpressure comes from the raw channel
+    #[cfg(feature = "futures")]
+    pub fn into_sink(self) -> PacketSink {
+        PacketSink::new(self)
+    }
+}
+
+impl<T: RawTransport> LwPkt<T> {
+    /// [`LwPkt`] over a user provided transport instead of the [`LwPktRaw`] channels, the
+    /// write buffer must hold [`max_frame_len`] bytes as for [`LwPkt::new`]
+    pub fn with_transport(
+        read_buffer: LwRb,
+        write_buffer: LwRb,
+        transport: T,
+    ) -> Result<Self, Error> {
+        debug_assert!(
+            !read_buffer.overlaps(&write_buffer),
+            "read and write buffers share memory"
+        );
+
+        check_write_buffer(&write_buffer)?;
+
+        Self::init(read_buffer, write_buffer, transport)
+    }
+
+    fn init(read_buffer: LwRb, write_buffer: LwRb, transport: T) -> Result<Self, Error> {
+        let mut read_buffer = Box::pin(read_buffer);
+        let mut write_buffer = Box::pin(write_buffer);
+        let rx_rb = &mut read_buffer.lwrb as *mut ffi::lwrb;
+        let tx_rb = &mut write_buffer.lwrb as *mut ffi::lwrb;
+
+        let mut result = Self::with_context(
+            Context::Owned(Box::pin(ffi::lwpkt::default())),
+            Some((read_buffer, write_buffer)),
+            transport,
+        );
+
+        let res = unsafe { ffi::lwpkt_init(result.lwpkt.as_mut_ptr(), tx_rb, rx_rb) };
+        check_result(res)?;
+
+        Ok(result)
+    }
+
+    fn with_context(lwpkt: Context, buffers: Option<Buffers>, transport: T) -> Self {
+        Self {
+            lwpkt,
+            _buffers: buffers,
+            transport,
+            valid: false,
+            last_status: ffi::lwpktr_t::lwpktWAITDATA,
+            window: Vec::new(),
+            bad_frame: None,
+            unread: VecDeque::new(),
+            staging: Vec::new(),
+            pool: PacketPool::new(),
+            max_data_len: LwPkt::MAX_PACKAGE_SIZE as usize,
+            pending: VecDeque::new(),
+            max_packets_per_read: None,
+            #[cfg(not(feature = "no-addr"))]
+            filter_by_address: false,
+            #[cfg(not(feature = "no-addr"))]
+            strict_from: false,
+            filtered: 0,
+            on_packet: None,
+            decode_trace: None,
+            #[cfg(test)]
+            ffi_calls: FfiCalls::default(),
+        }
+    }
+
+    fn rx_rb(&self) -> *mut ffi::lwrb {
+        self.lwpkt.rx_rb
+    }
+
+    fn tx_rb(&self) -> *mut ffi::lwrb {
+        self.lwpkt.tx_rb
+    }
+
+    pub fn read_buffer_size(&self) -> usize {
+        unsafe { (*self.rx_rb()).size }
+    }
+
+    pub fn write_buffer_size(&self) -> usize {
+        unsafe { (*self.tx_rb()).size }
+    }
+
+    /// Whether a packet with `payload_len` bytes of data fits the write buffer and
+    /// [`LwPkt::max_data_len`]. Room in the transport is not checked.
+    pub fn can_write(&self, payload_len: usize) -> bool {
+        let free = unsafe { ffi::lwrb_get_free(self.tx_rb()) };
+
+        payload_len <= self.max_data_len && frame_len(payload_len) <= free
+    }
+
+    /// Framed bytes still sitting in the write ring buffer, not handed to the raw channel
+    pub fn write_buffer_used(&self) -> usize {
+        unsafe { ffi::lwrb_get_full(self.tx_rb()) }
+    }
+
+    #[cfg(not(feature = "no-addr"))]
+    #[must_use = "the address is left unchanged on error"]
+    pub fn set_addres(&mut self, address: u8) -> Result<(), Error> {
+        let res = unsafe { ffi::lwpkt_set_addr(self.lwpkt.as_mut_ptr(), address) };
+
+        check_result(res)
+    }
+
+    #[cfg(not(feature = "no-addr"))]
+    pub fn address(&self) -> u8 {
+        self.lwpkt.addr
+    }
+
+    /// Drops decoded packets addressed neither to [`LwPkt::address`] nor to
+    /// [`BROADCAST_ADDRESS`], they never reach the read methods or the packet callback
+    #[cfg(not(feature = "no-addr"))]
+    pub fn set_filter_by_address(&mut self, filter: bool) {
+        self.filter_by_address = filter;
+    }
+
+    /// Rejects packets whose `from` is neither `0` nor [`LwPkt::address`] with
+    /// [`Error::FromMismatch`], lwpkt always puts the local address on the wire. Catches a
+    /// relayed or copied packet sent again with a stale `from`.
+    #[cfg(not(feature = "no-addr"))]
+    pub fn set_strict_from(&mut self, strict: bool) {
+        self.strict_from = strict;
+    }
+
+    /// Packets dropped so far because of [`LwPkt::set_filter_by_address`]
+    pub fn filtered_count(&self) -> usize {
+        self.filtered
+    }
+
+    /// Sets `address` and returns the previous one
+    #[cfg(not(feature = "no-addr"))]
+    pub fn swap_address(&mut self, address: u8) -> Result<u8, Error> {
+        let previous = self.address();
+        self.set_addres(address)?;
+
+        Ok(previous)
+    }
+
+    fn frame(&mut self, package: &Package) -> Result<(), Error> {
+        if package.data.len() > self.max_data_len {
+            return Err(Error::PayloadTooLarge {
+                len: package.data.len(),
+                max: self.max_data_len,
+            });
+        }
+
+        if package.cmd > self.max_cmd() {
+            return Err(Error::CmdOutOfRange {
+                cmd: package.cmd,
+                max: self.max_cmd(),
+            });
+        }
+
+        #[cfg(not(feature = "no-addr"))]
+        if self.strict_from && package.from != 0 && package.from != self.address() {
+            return Err(Error::FromMismatch {
+                from: package.from,
+                address: self.address(),
+            });
+        }
+
+        let pkt = self.lwpkt.as_mut_ptr();
+
+        // An empty payload passes the dangling but non-null pointer of an empty Vec, lwrb
+        // never dereferences it for a zero length write
+        #[cfg(all(not(feature = "no-addr"), not(feature = "flags")))]
+        let res = unsafe {
+            ffi::lwpkt_write(
+                pkt,
+                package.to,
+                package.cmd as _,
+                package.data.as_ptr() as *mut _,
+                package.data.len(),
+            )
+        };
+
+        #[cfg(all(not(feature = "no-addr"), feature = "flags"))]
+        let res = unsafe {
+            ffi::lwpkt_write(
+                pkt,
+                package.to,
+                package.flags as _,
+                package.cmd as _,
+                package.data.as_ptr() as *mut _,
+                package.data.len(),
+            )
+        };
+
+        #[cfg(all(feature = "no-addr", not(feature = "flags")))]
+        let res = unsafe {
+            ffi::lwpkt_write(
+                pkt,
+                package.cmd as _,
+                package.data.as_ptr() as *mut _,
+                package.data.len(),
+            )
+        };
+
+        #[cfg(all(feature = "no-addr", feature = "flags"))]
+        let res = unsafe {
+            ffi::lwpkt_write(
+                pkt,
+                package.flags as _,
+                package.cmd as _,
+                package.data.as_ptr() as *mut _,
+                package.data.len(),
+            )
+        };
+
+        // lwpkt_write only reports OK, ERR for bad arguments or ERRMEM when the frame does
+        // not fit the write buffer, there is no partial success to handle here
+        check_result(res)
+    }
+
+    /// Caps outgoing payloads below the compiled in [`LwPkt::MAX_PACKAGE_SIZE`], values are
+    /// clamped to `1..=MAX_PACKAGE_SIZE`. Incoming packets are not affected.
+    pub fn set_max_data_len(&mut self, len: usize) {
+        self.max_data_len = len.clamp(1, LwPkt::MAX_PACKAGE_SIZE as usize);
+    }
+
+    pub fn max_data_len(&self) -> usize {
+        self.max_data_len
+    }
+
+    /// Largest command the configured lwpkt command field can carry
+    pub fn max_cmd(&self) -> u32 {
+        match std::mem::size_of_val(&self.lwpkt.m.cmd) {
+            1 => u8::MAX as u32,
+            2 => u16::MAX as u32,
+            _ => u32::MAX,
+        }
+    }
+
+    /// Frames `package` and queues it for the [`LwPktRaw`] as a single chunk.
+    ///
+    /// ```
+    /// # #[cfg(feature = "async")] {
+    /// use std::io::Read;
+    ///
+    /// use lwpkt_rs::{LwPkt, LwRb, Package};
+    ///
+    /// let (mut lwpkt, mut raw) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+    /// lwpkt.write(Package { cmd: 0x10, from: 0, to: 0, flags: 0, data: vec![1, 2, 3] }).unwrap();
+    ///
+    /// let mut wire = Vec::new();
+    /// raw.read_to_end(&mut wire).unwrap();
+    /// assert_eq!(lwpkt_rs::decode_all(&wire).len(), 1);
+    /// # }
+    /// ```
+    #[must_use = "the packet is not sent on error"]
+    pub fn write(&mut self, package: Package) -> Result<(), Error> {
+        // The whole frame goes out as a single chunk, a full channel drops it entirely
+        // instead of leaving half a frame behind
+        match self.try_write(package)? {
+            Sent::All => Ok(()),
+            Sent::Partial { .. } => Err(Error::ErrorMem),
+        }
+    }
+
+    /// Same as [`LwPkt::write`] but a full channel hands the frame back instead of failing
+    pub fn try_write(&mut self, package: Package) -> Result<Sent, Error> {
+        let frame = self.encode(&package)?;
+
+        self.try_write_frame(frame)
+    }
+
+    /// Queues an already framed packet, typically the remainder of a [`LwPkt::try_write`]
+    pub fn try_write_frame(&mut self, frame: Vec<u8>) -> Result<Sent, Error> {
+        self.transport.send_bytes(frame)
+    }
+
+    /// Frames `package` and returns the wire bytes without going through the raw channel
+    pub fn encode(&mut self, package: &Package) -> Result<Vec<u8>, Error> {
+        self.frame(package)?;
+
+        let wb = self.tx_rb();
+
+        let len = unsafe { ffi::lwrb_get_full(wb) };
+        let mut frame = vec![0u8; len];
+        let res = unsafe { ffi::lwrb_read(wb, frame.as_mut_ptr() as *mut _, frame.len()) };
+        frame.truncate(res);
+
+        Ok(frame)
+    }
+
+    // Same as `encode` into the staging buffer instead of a new `Vec`
+    fn stage(&mut self, package: &Package) -> Result<&[u8], Error> {
+        self.frame(package)?;
+
+        let wb = self.tx_rb();
+
+        let len = unsafe { ffi::lwrb_get_full(wb) };
+        self.staging.resize(len, 0);
+        let res = unsafe { ffi::lwrb_read(wb, self.staging.as_mut_ptr() as *mut _, len) };
+        self.staging.truncate(res);
+
+        Ok(&self.staging)
+    }
+
+    /// Like [`LwPkt::write`] for a fixed `package` sent over and over: it is borrowed and
+    /// framed in a buffer kept across calls, the transport gets a copy in a buffer from
+    /// [`LwPkt::pool`]. The [`LwPktRaw`] hands the chunks it reads through
+    /// [`std::io::Read`] back to the pool, a steady stream of such writes allocates no
+    /// more. Returns `false` when the transport is full, nothing is sent then.
+    pub fn write_if_space(&mut self, package: &Package) -> Result<bool, Error> {
+        self.stage(package)?;
+
+        let mut frame = self.pool.take();
+        frame.extend_from_slice(&self.staging);
+
+        match self.transport.send_bytes(frame)? {
+            Sent::All => Ok(true),
+            Sent::Partial { remaining, .. } => {
+                self.pool.put(remaining);
+                Ok(false)
+            }
+        }
+    }
+
+    /// Frames `package` and writes it straight to `dst`, no [`LwPktRaw`] involved
+    pub fn write_to<W: std::io::Write>(
+        &mut self,
+        dst: &mut W,
+        package: Package,
+    ) -> Result<(), Error> {
+        let frame = self.stage(&package)?;
+
+        Ok(dst.write_all(frame)?)
+    }
+
+    /// Splits `data` into as many packets as needed, the payload is terminated by the first
+    /// packet shorter than [`LwPkt::max_data_len`] (an empty one if needed). Returns the
+    /// number of packets sent, use [`Reassembler`] to put them back together.
+    pub fn write_chunked(&mut self, to: u8, cmd: u32, data: &[u8]) -> Result<usize, Error> {
+        let max = self.max_data_len;
+
+        let mut sent = 0;
+        for chunk in data.chunks(max) {
+            self.write(Package {
+                cmd,
+                from: 0,
+                to,
+                flags: 0,
+                data: chunk.to_vec(),
+            })?;
+            sent += 1;
+        }
+
+        if data.len().is_multiple_of(max) {
+            self.write(Package {
+                cmd,
+                from: 0,
+                to,
+                flags: 0,
+                data: Vec::new(),
+            })?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+
+    /// Decodes everything the [`LwPktRaw`] sent so far, a partial frame is kept until the
+    /// rest arrives.
+    ///
+    /// ```
+    /// # #[cfg(feature = "async")] {
+    /// use std::io::Write;
+    ///
+    /// use lwpkt_rs::{LwPkt, LwRb, Package};
+    ///
+    /// let (mut lwpkt, mut raw) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+    /// let frame = lwpkt
+    ///     .encode(&Package { cmd: 0x10, from: 0, to: 0, flags: 0, data: b"split".to_vec() })
+    ///     .unwrap();
+    ///
+    /// raw.write_all(&frame[..4]).unwrap();
+    /// assert!(lwpkt.read().unwrap().is_empty());
+    ///
+    /// raw.write_all(&frame[4..]).unwrap();
+    /// let packets = lwpkt.read().unwrap();
+    /// assert_eq!(packets.len(), 1);
+    /// assert!(packets[0].data_eq(b"split"));
+    /// # }
+    /// ```
+    #[must_use = "decoded packets are dropped along with the result"]
+    pub fn read(&'_ mut self) -> Result<Vec<Package>, Error> {
+        let mut results = Vec::new();
+        self.read_into(&mut results)?;
+
+        Ok(results)
+    }
+
+    /// Decodes like [`LwPkt::read`] but only returns how many packets went through, queued
+    /// ones included. The payloads are dropped, their buffers go back to [`LwPkt::pool`].
+    pub fn count_packets(&mut self) -> Result<usize, Error> {
+        let mut count = 0;
+        for package in self.pending.drain(..) {
+            self.pool.put(package.data);
+            count += 1;
+        }
+
+        self.receive_with(|lwpkt, package| {
+            lwpkt.pool.put(package.data);
+            count += 1;
+        })?;
+
+        Ok(count)
+    }
+
+    /// Same as [`LwPkt::read`] with packets giving their payload buffer back to
+    /// [`LwPkt::pool`] once dropped, the decoder reuses it for the next packets
+    pub fn read_pooled(&mut self) -> Result<Vec<PooledPackage>, Error> {
+        let packets = self.read()?;
+
+        Ok(packets
+            .into_iter()
+            .map(|package| PooledPackage::new(package, self.pool.clone()))
+            .collect())
+    }
+
+    /// Payload buffers recycled by [`LwPkt::read_pooled`]
+    pub fn pool(&self) -> &PacketPool {
+        &self.pool
+    }
+
+    /// Same as [`LwPkt::read`] but reuses `out`, which is cleared first
+    pub fn read_into(&mut self, out: &mut Vec<Package>) -> Result<(), Error> {
+        out.clear();
+        self.receive()?;
+        out.extend(self.drain_pending());
+
+        Ok(())
+    }
+
+    /// Like [`LwPkt::read`] but tells apart an idle link from chunks that did not complete a
+    /// packet yet
+    pub fn read_outcome(&mut self) -> Result<ReadOutcome, Error> {
+        let received = self.receive()?;
+
+        Ok(if !self.pending.is_empty() {
+            ReadOutcome::Packets(self.drain_pending().collect())
+        } else if received {
+            ReadOutcome::Progressed
+        } else {
+            ReadOutcome::Idle
+        })
+    }
+
+    /// Decodes every chunk available on the transport into the pending queue, returns whether
+    /// any bytes arrived. Packets decoded before an error stay queued.
+    fn receive(&mut self) -> Result<bool, Error> {
+        self.receive_with(|lwpkt, package| lwpkt.pending.push_back(package))
+    }
+
+    /// Stops taking chunks from the transport once the packets queued and decoded reach
+    /// `max_packets_per_read`, a chunk already taken is decoded to its end.
+    fn receive_with(&mut self, mut decoded: impl FnMut(&mut Self, Package)) -> Result<bool, Error> {
+        let max = self.max_packets_per_read.unwrap_or(usize::MAX);
+        let mut count = self.pending.len();
+
+        let mut received = false;
+        while count < max {
+            let Some(buffer) = self.transport.recv_bytes()? else {
+                break;
+            };
+
+            // An empty write on the raw side, nothing arrived
+            if buffer.is_empty() {
+                continue;
+            }
+
+            received = true;
+            self.decode_bytes(&buffer, |lwpkt, package| {
+                count += 1;
+                decoded(lwpkt, package);
+            })?;
+        }
+
+        Ok(received)
+    }
+
+    // Queued packets a read hands out, at most `max_packets_per_read`
+    fn drain_pending(&mut self) -> std::collections::vec_deque::Drain<'_, Package> {
+        let count = self
+            .max_packets_per_read
+            .map_or(self.pending.len(), |max| max.min(self.pending.len()));
+
+        self.pending.drain(..count)
+    }
+
+    /// Caps how many packets [`LwPkt::read`] and the other read methods hand out per call,
+    /// `None` (the default) for no cap. The rest is kept for the next calls and no more
+    /// chunks are taken from the transport while the cap is reached, which bounds the memory
+    /// a flood of small frames can use.
+    pub fn set_max_packets_per_read(&mut self, max: Option<usize>) {
+        self.max_packets_per_read = max;
+    }
+
+    /// Same as [`LwPkt::read`] with the time each packet was decoded at. Packets left
+    /// queued by an earlier call, e.g. [`LwPkt::read_upto`], get the time of this call.
+    pub fn read_timed(&mut self) -> Result<Vec<(Instant, Package)>, Error> {
+        let mut timed = vec![];
+        let received = self.receive_with(|_, package| timed.push((Instant::now(), package)));
+
+        if let Err(e) = received {
+            // Queued like `read` would, the times are lost
+            self.pending
+                .extend(timed.into_iter().map(|(_, package)| package));
+            return Err(e);
+        }
+
+        let now = Instant::now();
+        let mut packets = self
+            .pending
+            .drain(..)
+            .map(|package| (now, package))
+            .chain(timed)
+            .collect::<Vec<_>>();
+
+        if let Some(max) = self.max_packets_per_read
+            && packets.len() > max
+        {
+            let rest = packets.split_off(max);
+            self.pending
+                .extend(rest.into_iter().map(|(_, package)| package));
+        }
+
+        Ok(packets)
+    }
+
+    /// Decodes `buffer` as if it came from the raw side, the packets are returned by the
+    /// next read. A decode error is returned once all of `buffer` went through, the packets
+    /// before and after the bad frame are queued.
+    pub fn feed(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        self.decode_bytes(buffer, |lwpkt, package| lwpkt.pending.push_back(package))?;
+
+        Ok(())
+    }
+
+    /// Decodes `bytes` in place and hands every packet to `on_packet` instead of queuing it
+    /// for the read methods. Returns the number of bytes consumed, always all of them. A
+    /// decode error is returned instead once the whole slice went through, the packets
+    /// after the bad frame are still handed to `on_packet`.
+    pub fn decode_slice(
+        &mut self,
+        bytes: &[u8],
+        mut on_packet: impl FnMut(&Package),
+    ) -> Result<usize, Error> {
+        self.decode_bytes(bytes, |_, package| on_packet(&package))
+    }
+
+    /// Encodes `package` and decodes the frame on this instance, no transport involved. A
+    /// power-on self test of the whole encode and decode path. Fails with
+    /// [`Error::InProgress`] while a received frame is being decoded, the packet is never
+    /// queued for the read methods nor handed to the callbacks.
+    pub fn loopback_roundtrip(&mut self, package: Package) -> Result<Package, Error> {
+        let idle = self.lwpkt.m.state == ffi::lwpkt_state_t::LWPKT_STATE_START;
+        if !idle || unsafe { ffi::lwrb_get_full(self.rx_rb()) } != 0 {
+            return Err(Error::InProgress);
+        }
+
+        let frame = self.encode(&package)?;
+
+        let mut decoded = None;
+        let mut from = 0;
+        while from < frame.len() {
+            let written = self.write_read_buffer(&frame[from..]);
+            if written == 0 {
+                return Err(Error::ErrorMem);
+            }
+            from += written;
+
+            if let Some(package) = self.read_frame()? {
+                decoded = Some(package);
+            }
+        }
+
+        decoded.ok_or(Error::ERR)
+    }
+
+    fn decode_bytes(
+        &mut self,
+        bytes: &[u8],
+        mut decoded: impl FnMut(&mut Self, Package),
+    ) -> Result<usize, Error> {
+        // A trace sees every decoder step when the bytes go in one at a time
+        let step = if self.decode_trace.is_some() {
+            1
+        } else {
+            bytes.len()
+        };
+
+        // A decode error does not stop it, the bytes were already taken from the transport.
+        // The first error is returned once the whole chunk went through.
+        let mut error = None;
+
+        let mut from = 0;
+        while from < bytes.len() {
+            let to = bytes.len().min(from + step);
+            let written = self.write_read_buffer(&bytes[from..to]);
+
+            // Drain the read buffer before writing more, the next packets may already be in
+            loop {
+                match self.decode_step() {
+                    Ok(Some(package)) => decoded(self, package),
+                    Ok(None) => break,
+                    Err(e) => {
+                        error.get_or_insert(e);
+                    }
+                }
+            }
+
+            // Only possible with a read buffer too small to hold a single byte
+            if written == 0 {
+                return Err(error.unwrap_or(Error::ErrorMem));
+            }
+
+            from += written;
+        }
+
+        error.map_or(Ok(from), Err)
+    }
+
+    fn write_read_buffer(&mut self, bytes: &[u8]) -> usize {
+        let written =
+            unsafe { ffi::lwrb_write(self.rx_rb(), bytes.as_ptr() as *mut _, bytes.len()) };
+        self.unread.extend(&bytes[..written]);
+        #[cfg(test)]
+        {
+            self.ffi_calls.lwrb_write += 1;
+        }
+
+        written
+    }
+
+    /// Decodes the next packet that is not filtered out, `None` once the read buffer is
+    /// drained
+    fn decode_step(&mut self) -> Result<Option<Package>, Error> {
+        loop {
+            let Some(package) = self.read_frame()? else {
+                return Ok(None);
+            };
+
+            if !self.accepts(&package) {
+                self.filtered += 1;
+                continue;
+            }
+
+            if let Some(on_packet) = self.on_packet.as_mut() {
+                on_packet(&package);
+            }
+
+            return Ok(Some(package));
+        }
+    }
+
+    #[cfg(not(feature = "no-addr"))]
+    fn accepts(&self, package: &Package) -> bool {
+        !self.filter_by_address || package.to == self.address() || package.to == BROADCAST_ADDRESS
+    }
+
+    #[cfg(feature = "no-addr")]
+    fn accepts(&self, _package: &Package) -> bool {
+        true
+    }
+
+    /// Single `lwpkt_read` pass over the read buffer, `None` when no packet completed
+    fn read_frame(&mut self) -> Result<Option<Package>, Error> {
+        let rb = self.rx_rb();
+
+        // Keep a copy of what the decoder consumes to recover a frame failing its CRC. Only
+        // peeked when the read buffer changed behind our back, e.g. an adopted C context.
+        let full = unsafe { ffi::lwrb_get_full(rb) };
+        if self.unread.len() != full {
+            let mut buffered = vec![0u8; full];
+            let len =
+                unsafe { ffi::lwrb_peek(rb, 0, buffered.as_mut_ptr() as *mut _, buffered.len()) };
+            self.unread.clear();
+            self.unread.extend(&buffered[..len]);
+            #[cfg(test)]
+            {
+                self.ffi_calls.lwrb_peek += 1;
+            }
+        }
+
+        let state = self.lwpkt.m.state;
+        let status = unsafe { ffi::lwpkt_read(self.lwpkt.as_mut_ptr()) };
+        #[cfg(test)]
+        {
+            self.ffi_calls.lwpkt_read += 1;
+        }
+
+        let consumed = full - unsafe { ffi::lwrb_get_full(rb) };
+        self.window.extend(self.unread.drain(..consumed));
+        self.trace_step(state, status);
+
+        match status {
+            ffi::lwpktr_t::lwpktINPROG => {}
+            ffi::lwpktr_t::lwpktERRCRC => {
+                let start = config().start_byte;
+                let from = self.window.iter().position(|b| *b == start).unwrap_or(0);
+                self.bad_frame = Some(self.window.split_off(from));
+                self.window.clear();
+            }
+            _ => self.window.clear(),
+        }
+
+        if status != ffi::lwpktr_t::lwpktWAITDATA {
+            self.valid = status == ffi::lwpktr_t::lwpktVALID;
+            self.last_status = status;
+        }
+
+        match status {
+            ffi::lwpktr_t::lwpktVALID => {
+                let view = self.current_view();
+                let mut data = self.pool.take();
+                data.extend_from_slice(view.data());
+                let package = Package {
+                    cmd: view.cmd(),
+                    from: view.from(),
+                    to: view.to(),
+                    flags: view.flags(),
+                    data,
+                };
+
+                Ok(Some(package))
+            }
+            ffi::lwpktr_t::lwpktWAITDATA => Ok(None),
+            ffi::lwpktr_t::lwpktINPROG => Ok(None),
+            e => Err(e.into()),
+        }
+    }
+
+    // Reports the steps of the last `lwpkt_read` that started in `before`, every step is
+    // seen only when it consumed a single byte
+    fn trace_step(&mut self, before: ffi::lwpkt_state_t::Type, status: ffi::lwpktr_t::Type) {
+        use ffi::lwpkt_state_t::*;
+
+        let Some(trace) = self.decode_trace.as_mut() else {
+            return;
+        };
+
+        let m = &self.lwpkt.m;
+        let parsed = |state| matches!(state, LWPKT_STATE_DATA | LWPKT_STATE_CRC | LWPKT_STATE_STOP);
+
+        if before == LWPKT_STATE_START && m.state != LWPKT_STATE_START {
+            trace(DecodeEvent::StartByte);
+        }
+        if !parsed(before) && parsed(m.state) {
+            trace(DecodeEvent::Length(m.len));
+        }
+
+        let got = self.window.last().copied().unwrap_or_default();
+        match status {
+            ffi::lwpktr_t::lwpktVALID => trace(DecodeEvent::Packet {
+                cmd: m.cmd as u32,
+                len: m.len,
+            }),
+            #[cfg(not(feature = "no-crc"))]
+            ffi::lwpktr_t::lwpktERRCRC => {
+                // The CRC covers everything between the start byte and itself
+                let start = config().start_byte;
+                let from = self.window.iter().position(|b| *b == start).unwrap_or(0);
+                let covered = &self.window[(from + 1).min(self.window.len())..];
+                let expected = crc(&covered[..covered.len().saturating_sub(1)]);
+
+                trace(DecodeEvent::CrcMismatch { expected, got });
+            }
+            ffi::lwpktr_t::lwpktERRSTOP => trace(DecodeEvent::StopByteMismatch { got }),
+            ffi::lwpktr_t::lwpktWAITDATA | ffi::lwpktr_t::lwpktINPROG => {}
+            e => trace(DecodeEvent::Error(e.into())),
+        }
+    }
+
+    /// Called with every step of the decoder, to debug frames that do not decode. While it
+    /// is set the decoder is fed one byte at a time.
+    pub fn set_decode_trace<F: FnMut(DecodeEvent) + Send + 'static>(&mut self, trace: F) {
+        self.decode_trace = Some(Box::new(trace));
+    }
+
+    /// Reads at most `max_bytes` straight from `src` into the decoder, no [`LwPktRaw`]
+    /// involved. Returns the packets completed so far.
+    pub fn read_from<R: std::io::Read>(
+        &mut self,
+        src: &mut R,
+        max_bytes: usize,
+    ) -> Result<Vec<Package>, Error> {
+        let mut buffer = vec![0u8; max_bytes];
+
+        let len = match src.read(&mut buffer) {
+            Ok(len) => len,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => 0,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => 0,
+            Err(e) => return Err(e.into()),
+        };
+
+        self.feed(&buffer[..len])?;
+
+        Ok(self.pending.drain(..).collect())
+    }
+
+    /// Same as [`LwPkt::read`] but returns at most `max` packets, the rest is kept for the
+    /// next call
+    pub fn read_upto(&mut self, max: usize) -> Result<Vec<Package>, Error> {
+        self.receive()?;
+
+        let count = max.min(self.pending.len());
+        Ok(self.pending.drain(..count).collect())
+    }
+
+    /// Same as [`LwPkt::read_upto`] into a fixed capacity vector, the flag tells whether
+    /// more packets are waiting
+    #[cfg(feature = "heapless")]
+    pub fn read_into_array<const N: usize>(
+        &mut self,
+    ) -> Result<(heapless::Vec<Package, N>, bool), Error> {
+        self.receive()?;
+
+        let mut packets = heapless::Vec::new();
+        while !packets.is_full() {
+            let Some(package) = self.pending.pop_front() else {
+                break;
+            };
+            // Cannot fail, there is room left
+            let _ = packets.push(package);
+        }
+
+        Ok((packets, !self.pending.is_empty()))
+    }
+
+    pub fn get_data(&self) -> &[u8] {
+        self.current_view().data()
+    }
+
+    pub fn get_cmd(&self) -> u32 {
+        self.lwpkt.m.cmd as u32
+    }
+
+    /// Status of the last decoder step that consumed bytes: [`Error::Valid`],
+    /// [`Error::InProgress`] or a decode error. [`Error::WaitData`] until bytes arrive.
+    pub fn last_status(&self) -> Error {
+        self.last_status.into()
+    }
+
+    /// Raw bytes of the last frame rejected with [`Error::ErrorCRC`], from its start byte up
+    /// to the CRC byte. The stop byte is never read, the frame is dropped as soon as the CRC
+    /// does not match.
+    pub fn last_bad_frame(&self) -> Option<&[u8]> {
+        self.bad_frame.as_deref()
+    }
+
+    /// Last decoded packet, `None` until a packet is decoded and again once the decoder
+    /// moves on to the next frame
+    pub fn current(&self) -> Option<CurrentPacket<'_>> {
+        self.valid.then(|| self.current_view())
+    }
+
+    fn current_view(&self) -> CurrentPacket<'_> {
+        CurrentPacket { lwpkt: &self.lwpkt }
+    }
+
+    /// Bytes still missing to complete the frame being decoded: the rest of the payload
+    /// plus CRC and stop byte. `None` when no frame is in progress or its length header is
+    /// not parsed yet.
+    pub fn bytes_needed(&self) -> Option<usize> {
+        let m = &self.lwpkt.m;
+        let crc = usize::from(config().use_crc);
+
+        match m.state {
+            ffi::lwpkt_state_t::LWPKT_STATE_DATA => Some(m.len.saturating_sub(m.index) + crc + 1),
+            ffi::lwpkt_state_t::LWPKT_STATE_CRC => Some(crc + 1),
+            ffi::lwpkt_state_t::LWPKT_STATE_STOP => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Payload length of the next packet a read hands out: a decoded packet still queued,
+    /// else the frame being decoded. `None` until the length header of that frame is parsed.
+    pub fn next_packet_len(&self) -> Option<usize> {
+        if let Some(package) = self.pending.front() {
+            return Some(package.data.len());
+        }
+
+        let m = &self.lwpkt.m;
+        match m.state {
+            ffi::lwpkt_state_t::LWPKT_STATE_DATA
+            | ffi::lwpkt_state_t::LWPKT_STATE_CRC
+            | ffi::lwpkt_state_t::LWPKT_STATE_STOP => Some(m.len),
+            _ => None,
+        }
+    }
+
+    /// Called with every decoded packet as soon as it is decoded, the packet is still
+    /// queued for the read methods
+    pub fn set_on_packet<F: FnMut(&Package) + Send + 'static>(&mut self, on_packet: F) {
+        self.on_packet = Some(Box::new(on_packet));
+    }
+
+    /// Drops the frame being decoded and skips to the next start byte, the bytes from there
+    /// on are decoded again and queued for the read methods. Searches both the bytes a
+    /// decode error left in the read buffer and the ones the dropped frame already took, a
+    /// frame with a corrupted length swallows the frames after it. Returns how many bytes
+    /// were dropped.
+    pub fn resync(&mut self) -> Result<usize, Error> {
+        let rb = self.rx_rb();
+
+        let mut buffered = vec![0u8; unsafe { ffi::lwrb_get_full(rb) }];
+        let len = unsafe { ffi::lwrb_read(rb, buffered.as_mut_ptr() as *mut _, buffered.len()) };
+        buffered.truncate(len);
+
+        unsafe { ffi::lwpkt_reset(self.lwpkt.as_mut_ptr()) };
+        self.valid = false;
+        self.unread.clear();
+
+        // The start byte of the dropped frame itself does not count
+        let start = config().start_byte;
+        let mut bytes = std::mem::take(&mut self.window);
+        let from = bytes
+            .iter()
+            .position(|b| *b == start)
+            .map_or(bytes.len(), |i| i + 1);
+        bytes.extend(buffered);
+
+        let dropped = bytes[from..]
+            .iter()
+            .position(|b| *b == start)
+            .map_or(bytes.len(), |i| from + i);
+        self.feed(&bytes[dropped..])?;
+
+        Ok(dropped)
+    }
+}
+
+impl<T: RawTransport + std::fmt::Debug> std::fmt::Debug for LwPkt<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut s = f.debug_struct("LwPkt");
+
+        #[cfg(not(feature = "no-addr"))]
+        s.field("address", &self.address());
+
+        s.field("read_buffer_size", &self.read_buffer_size())
+            .field("write_buffer_size", &self.write_buffer_size())
+            .field("transport", &self.transport)
+            .field("pending", &self.pending.len())
+            .finish_non_exhaustive()
+    }
+}
+
+pub struct CurrentPacket<'a> {
+    lwpkt: &'a ffi::lwpkt,
+}
+
+impl<'a> CurrentPacket<'a> {
+    #[cfg(not(feature = "no-addr"))]
+    pub fn from(&self) -> u8 {
+        self.lwpkt.m.from
+    }
+
+    #[cfg(not(feature = "no-addr"))]
+    pub fn to(&self) -> u8 {
+        self.lwpkt.m.to
+    }
+
+    #[cfg(feature = "no-addr")]
+    pub fn from(&self) -> u8 {
+        0
+    }
+
+    #[cfg(feature = "no-addr")]
+    pub fn to(&self) -> u8 {
+        0
+    }
+
+    pub fn cmd(&self) -> u32 {
+        self.lwpkt.m.cmd as u32
+    }
+
+    #[cfg(feature = "flags")]
+    pub fn flags(&self) -> u32 {
+        self.lwpkt.m.flags
+    }
+
+    #[cfg(not(feature = "flags"))]
+    pub fn flags(&self) -> u32 {
+        0
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        // A corrupted length header must not turn into an out of bounds slice
+        let len = self.lwpkt.m.len.min(self.lwpkt.data.len());
+        &self.lwpkt.data[..len]
+    }
+
+    pub fn to_package(&self) -> Package {
+        Package {
+            cmd: self.cmd(),
+            from: self.from(),
+            to: self.to(),
+            flags: self.flags(),
+            data: self.data().to_vec(),
+        }
+    }
+}
+
+/// Extra producer feeding the same [`LwPkt`] as the [`LwPktRaw`] it was taken from
+#[cfg(feature = "async")]
+#[derive(Clone)]
+pub struct RawSender {
+    to_pkt: Sender<Vec<u8>>,
+}
+
+#[cfg(feature = "async")]
+impl RawSender {
+    pub async fn write_chunk(&self, data: Vec<u8>) -> Result<(), Error> {
+        self.to_pkt
+            .send(data)
+            .await
+            .map_err(|_| Error::ErrorClosedRaw)
+    }
+}
+
+/// Copy of the transmitted byte stream, see [`LwPktRaw::tee`]
+#[cfg(feature = "async")]
+pub struct RawReceiver {
+    from_pkt: Receiver<Vec<u8>>,
+}
+
+#[cfg(feature = "async")]
+impl RawReceiver {
+    pub async fn read_chunk(&self) -> Result<Vec<u8>, Error> {
+        self.from_pkt
+            .recv()
+            .await
+            .map_err(|_| Error::ErrorClosedRaw)
+    }
+
+    pub fn try_read_chunk(&self) -> Result<Option<Vec<u8>>, Error> {
+        match self.from_pkt.try_recv() {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(async_channel::TryRecvError::Empty) => Ok(None),
+            Err(async_channel::TryRecvError::Closed) => Err(Error::ErrorClosedRaw),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl LwPktRaw {
+    const TAP_CAPACITY: usize = 64;
+
+    /// Closes both raw channels and the taps, a pending async read on the [`LwPkt`] side
+    /// wakes up with [`Error::ErrorClosedRaw`]. Chunks not read yet are dropped, the ones
+    /// queued for the [`LwPkt`] can still be decoded by it.
+    pub fn close(&mut self) {
+        self.to_pkt.close();
+        self.from_pkt.close();
+
+        while self.from_pkt.try_recv().is_ok() {}
+        self.last_read.clear();
+
+        for tap in self.taps.drain(..) {
+            tap.close();
+        }
+    }
+
+    /// Subscribes to the transmitted bytes.
+    ///
+    /// Every chunk this `LwPktRaw` takes from the [`LwPkt`] (through `read_chunk` or
+    /// `std::io::Read`) is also copied to each subscriber, in the same order. Subscribers
+    /// never apply backpressure: a chunk is dropped for a subscriber whose queue is full, and
+    /// dropped subscribers are forgotten.
+    pub fn tee(&mut self) -> RawReceiver {
+        let (tx, rx) = async_channel::bounded(Self::TAP_CAPACITY);
+        self.taps.push(tx);
+
+        RawReceiver { from_pkt: rx }
+    }
+
+    fn forward_taps(&mut self, chunk: &[u8]) {
+        self.taps.retain(|tap| match tap.try_send(chunk.to_vec()) {
+            Ok(()) => true,
+            Err(async_channel::TrySendError::Full(_)) => {
+                log::warn!("lwpkt tee is full, dropping {} bytes", chunk.len());
+                true
+            }
+            Err(async_channel::TrySendError::Closed(_)) => false,
+        });
+    }
+
+    /// Bytes the [`LwPkt`] sent that were not read yet. The queued chunks are moved into
+    /// the leftover buffer to count them, which frees their room in the channel.
+    pub fn pending_bytes(&mut self) -> usize {
+        while let Ok(chunk) = self.from_pkt.try_recv() {
+            self.forward_taps(&chunk);
+            self.last_read.extend_from_slice(&chunk);
+            self.pool.put(chunk);
+        }
+
+        self.last_read.len()
+    }
+
+    /// Drops the leftover of a previous partial read, chunks still in the channel are kept
+    pub fn clear(&mut self) {
+        self.last_read.clear();
+    }
+
+    /// Chunks that can still be queued for the [`LwPkt`] before non-blocking writes come
+    /// back empty
+    pub fn pkt_channel_capacity_remaining(&self) -> usize {
+        self.to_pkt
+            .capacity()
+            .unwrap_or(0)
+            .saturating_sub(self.to_pkt.len())
+    }
+
+    /// `false` once the [`LwPkt`] half is dropped
+    pub fn is_pkt_connected(&self) -> bool {
+        !self.to_pkt.is_closed()
+    }
+
+    pub fn sender(&self) -> RawSender {
+        RawSender {
+            to_pkt: self.to_pkt.clone(),
+        }
+    }
+
+    pub async fn read_chunk(&mut self) -> Result<Vec<u8>, Error> {
+        // Leftover of a previous `std::io::Read` call goes first to keep the byte order
+        if !self.last_read.is_empty() {
+            return Ok(std::mem::take(&mut self.last_read));
+        }
+
+        let chunk = self
+            .from_pkt
+            .recv()
+            .await
+            .map_err(|_| Error::ErrorClosedRaw)?;
+        self.forward_taps(&chunk);
+
+        Ok(chunk)
+    }
+
+    pub async fn write_chunk(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.to_pkt
+            .send(data)
+            .await
+            .map_err(|_| Error::ErrorClosedRaw)
+    }
+
+    /// Non-blocking [`std::io::Write::write`] that moves `data` into the channel instead of
+    /// copying it. A full channel drops `data` and returns [`Error::ErrorMem`].
+    pub fn write_owned(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        match self.to_pkt.try_send(data) {
+            Ok(()) => Ok(()),
+            Err(async_channel::TrySendError::Full(_)) => Err(Error::ErrorMem),
+            Err(async_channel::TrySendError::Closed(_)) => Err(Error::ErrorClosedRaw),
+        }
+    }
+
+    /// Queues `bytes` for the [`LwPkt`] as line noise, to exercise decode errors and
+    /// recovery. Same as [`LwPktRaw::write_owned`] otherwise.
+    #[cfg(feature = "testing")]
+    pub fn inject_garbage(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        self.write_owned(bytes.to_vec())
+    }
+
+    /// [`LwPktRaw::write_owned`] for `Bytes`, the allocation is reused when `data` is the
+    /// only handle to it
+    #[cfg(feature = "bytes")]
+    pub fn write_bytes(&mut self, data: bytes::Bytes) -> Result<(), Error> {
+        self.write_owned(data.into())
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::fmt::Debug for LwPktRaw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LwPktRaw")
+            .field("from_pkt", &self.from_pkt.len())
+            .field("to_pkt", &self.to_pkt.len())
+            .field("last_read", &self.last_read.len())
+            .field("taps", &self.taps.len())
+            .finish()
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::io::Read for LwPktRaw {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut readed = 0usize;
+        if !self.last_read.is_empty() {
+            match buf.len().cmp(&self.last_read.len()) {
+                std::cmp::Ordering::Less => {
+                    buf.copy_from_slice(&self.last_read[..buf.len()]);
+                    self.last_read = self.last_read[buf.len()..].to_vec();
+                    return Ok(buf.len());
+                }
+                std::cmp::Ordering::Equal => {
+                    buf.copy_from_slice(&self.last_read);
+                    self.pool.put(std::mem::take(&mut self.last_read));
+                    return Ok(buf.len());
+                }
+                std::cmp::Ordering::Greater => {
+                    buf[..self.last_read.len()].copy_from_slice(&self.last_read);
+                    readed = self.last_read.len();
+                    self.pool.put(std::mem::take(&mut self.last_read));
+                }
+            }
+        }
+
+        loop {
+            match self.from_pkt.try_recv() {
+                Ok(src) => {
+                    self.forward_taps(&src);
+                    let buffer = &mut buf[readed..];
+                    match buffer.len().cmp(&src.len()) {
+                        std::cmp::Ordering::Less => {
+                            buffer.copy_from_slice(&src[..buffer.len()]);
+                            self.last_read = src[buffer.len()..].to_vec();
+                            readed += buffer.len();
+                            return Ok(readed);
+                        }
+                        std::cmp::Ordering::Equal => {
+                            buffer.copy_from_slice(&src);
+                            readed += buffer.len();
+                            self.pool.put(src);
+                            return Ok(readed);
+                        }
+                        std::cmp::Ordering::Greater => {
+                            buffer[..src.len()].copy_from_slice(&src);
+                            readed += src.len();
+                            self.pool.put(src);
+                        }
+                    }
+                }
+                Err(async_channel::TryRecvError::Empty) => break,
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::BrokenPipe,
+                        e.to_string(),
+                    ));
+                }
+            }
+        }
+
+        Ok(readed)
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::io::Write for LwPktRaw {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.to_pkt.try_send(buf.to_vec()) {
+            Ok(()) => Ok(buf.len()),
+            Err(async_channel::TrySendError::Full(_)) => Ok(0),
+            Err(async_channel::TrySendError::Closed(v)) => Err(std::io::Error::new(
+                std::io::ErrorKind::BrokenPipe,
+                async_channel::TrySendError::Closed(v).to_string(),
+            )),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(feature = "async")]
+    use std::io::{Read, Write};
+
+    use crate::{LwPkt, LwRb};
+
+    #[test]
+    fn config_test() {
+        let config = crate::config();
+
+        assert_eq!(config.max_data_len, LwPkt::MAX_PACKAGE_SIZE as usize);
+        assert_eq!(config.use_flags, cfg!(feature = "flags"));
+        assert_eq!(config.use_crc, cfg!(not(feature = "no-crc")));
+        assert_eq!(config.use_addr, cfg!(not(feature = "no-addr")));
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn address_roundtrip_test() {
+        let (mut sender, mut sender_raw) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let (mut receiver, mut receiver_raw) = LwPkt::with_sizes(1024, 1024).unwrap();
+        sender.set_addres(0x11).unwrap();
+        receiver.set_addres(0x22).unwrap();
+
+        sender
+            .write(crate::Package {
+                cmd: 0x40,
+                from: 0,
+                to: 0x22,
+                flags: 0,
+                data: b"addressed".to_vec(),
+            })
+            .unwrap();
+
+        let mut wire = vec![];
+        sender_raw.read_to_end(&mut wire).unwrap();
+        receiver_raw.write_all(&wire).unwrap();
+
+        assert_eq!(
+            receiver.read().unwrap(),
+            vec![crate::Package {
+                cmd: 0x40,
+                from: 0x11,
+                to: 0x22,
+                flags: 0,
+                data: b"addressed".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn corrupted_payload_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let mut frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x40,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"payload".to_vec(),
+            })
+            .unwrap();
+        // Last payload byte, right before the CRC when enabled
+        let crc_len = usize::from(crate::config().use_crc);
+        let last = frame.len() - 2 - crc_len;
+        frame[last] ^= 0x01;
+
+        let res = lwpkt.feed(&frame);
+
+        // Without a CRC nothing catches it
+        if cfg!(not(feature = "no-crc")) {
+            assert_eq!(res, Err(crate::Error::ErrorCRC));
+            assert!(lwpkt.read().unwrap().is_empty());
+        } else {
+            res.unwrap();
+            assert!(lwpkt.read().unwrap()[0].data_eq(b"payloae"));
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn init_test() {
+        let rb = LwRb::new(1024);
+        let wb = LwRb::new(1024);
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        lwpkt.set_addres(0x12).unwrap();
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec(),
+            })
+            .unwrap();
+
+        let mut buffer = vec![];
+        raw_pkt.read_to_end(&mut buffer).unwrap();
+
+        raw_pkt.write_all(&buffer).unwrap();
+
+        let s = lwpkt.read().unwrap();
+
+        assert_eq!(
+            (*s.first().unwrap()),
+            crate::Package {
+                cmd: 0x85,
+                from: 0x12,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec()
+            }
+        )
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", feature = "no-addr"))]
+    fn no_addr_test() {
+        let rb = LwRb::new(1024);
+        let wb = LwRb::new(1024);
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0x12,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec(),
+            })
+            .unwrap();
+
+        let mut buffer = vec![];
+        raw_pkt.read_to_end(&mut buffer).unwrap();
+
+        // start, flags, cmd, len, data, crc, stop
+        let crc_len = if crate::config().use_crc { 1 } else { 0 };
+        let flags_len = if crate::config().use_flags { 1 } else { 0 };
+        assert_eq!(
+            buffer.len(),
+            3 + flags_len + b"some hello".len() + crc_len + 1
+        );
+        assert_eq!(buffer[1 + flags_len], 0x85);
+
+        raw_pkt.write_all(&buffer).unwrap();
+
+        let s = lwpkt.read().unwrap();
+
+        assert_eq!(
+            (*s.first().unwrap()),
+            crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"some hello".to_vec()
+            }
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn payload_too_large_test() {
+        let rb = LwRb::new(1024);
+        let wb = LwRb::new(1024);
+
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        let max = LwPkt::MAX_PACKAGE_SIZE as usize;
+        let res = lwpkt.write(crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: vec![0u8; max + 1],
+        });
+
+        assert_eq!(
+            res,
+            Err(crate::Error::PayloadTooLarge { len: max + 1, max })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn max_payload_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let max = LwPkt::MAX_PACKAGE_SIZE as usize;
+        let data = (0..max).map(|i| i as u8).collect::<Vec<_>>();
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: data.clone(),
+            })
+            .unwrap();
+
+        let s = lwpkt.read().unwrap();
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].data, data);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", feature = "testing"))]
+    fn peek_outgoing_test() {
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert!(lwpkt.peek_outgoing().is_empty());
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: b"golden".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        lwpkt.write(package).unwrap();
+
+        assert_eq!(lwpkt.peek_outgoing(), frame);
+        assert_eq!(lwpkt.peek_outgoing(), frame);
+        assert_eq!(raw_pkt.from_pkt.try_recv().unwrap(), frame);
+        assert!(lwpkt.peek_outgoing().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn max_data_len_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert_eq!(lwpkt.max_data_len(), LwPkt::MAX_PACKAGE_SIZE as usize);
+
+        lwpkt.set_max_data_len(usize::MAX);
+        assert_eq!(lwpkt.max_data_len(), LwPkt::MAX_PACKAGE_SIZE as usize);
+
+        lwpkt.set_max_data_len(16);
+        let mut package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: vec![0u8; 17],
+        };
+        assert_eq!(
+            lwpkt.encode(&package),
+            Err(crate::Error::PayloadTooLarge { len: 17, max: 16 })
+        );
+
+        package.data.truncate(16);
+        lwpkt.write(package).unwrap();
+        assert_eq!(lwpkt.read().unwrap()[0].data.len(), 16);
+
+        let mut reassembler = crate::Reassembler::with_max_len(16);
+        lwpkt.write_chunked(0x11, 0x86, &[1u8; 40]).unwrap();
+
+        let mut payload = None;
+        while payload.is_none() {
+            for package in lwpkt.read().unwrap() {
+                payload = reassembler.push(package);
+            }
+        }
+        assert_eq!(payload.unwrap().data, vec![1u8; 40]);
+
+        // Zero would leave write_chunked nothing to split by
+        lwpkt.set_max_data_len(0);
+        assert_eq!(lwpkt.max_data_len(), 1);
+        assert_eq!(lwpkt.write_chunked(0x11, 0x86, b"ab"), Ok(3));
+        assert_eq!(lwpkt.read().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn io_error_test() {
+        let closed = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        assert_eq!(crate::Error::from(closed), crate::Error::ErrorClosedRaw);
+
+        let e = crate::Error::from(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "serial port timeout",
+        ));
+        assert_eq!(
+            e,
+            crate::Error::Io {
+                kind: std::io::ErrorKind::TimedOut,
+                message: "serial port timeout".to_string()
+            }
+        );
+        assert!(e.to_string().contains("serial port timeout"));
+
+        let back = std::io::Error::from(e);
+        assert_eq!(back.kind(), std::io::ErrorKind::TimedOut);
+        assert_eq!(back.to_string(), "serial port timeout");
+
+        let closed = std::io::Error::from(crate::Error::ErrorClosedRaw);
+        assert_eq!(closed.kind(), std::io::ErrorKind::BrokenPipe);
+        let mem = std::io::Error::from(crate::Error::ErrorMem);
+        assert_eq!(mem.kind(), std::io::ErrorKind::OutOfMemory);
+
+        let crc = std::io::Error::from(crate::Error::ErrorCRC);
+        assert_eq!(crc.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(crc.to_string(), crate::Error::ErrorCRC.to_string());
+    }
+
+    #[test]
+    #[cfg(feature = "reliable")]
+    fn reliable_test() {
+        // Loopback, the link acknowledges its own packets
+        let lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        let mut link = crate::ReliableLink::new(lwpkt, 0x7F);
+        link.set_timeout(std::time::Duration::ZERO);
+        link.set_max_retries(1);
+
+        let package = crate::Package {
+            cmd: 0x11,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"reliable".to_vec(),
+        };
+        let handle = link.send_reliable(package.clone()).unwrap();
+        assert_eq!(handle.try_acked(), None);
+
+        // The packet itself and, with a zero timeout, its retransmission
+        assert_eq!(link.poll().unwrap(), vec![package]);
+        assert_eq!(link.in_flight(), 1);
+
+        // The duplicate is acknowledged but not handed out again, the first ACK resolves
+        assert_eq!(link.poll().unwrap(), vec![]);
+        assert_eq!(handle.try_acked(), Some(Ok(())));
+        assert_eq!(link.in_flight(), 0);
+
+        assert_eq!(
+            link.send_reliable(crate::Package {
+                cmd: 0x7F,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![],
+            })
+            .err(),
+            Some(crate::Error::AckCmdReserved { cmd: 0x7F })
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "reliable")]
+    fn reliable_send_error_test() {
+        let (lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let (mut peer, _peer_raw) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let mut link = crate::ReliableLink::new(lwpkt, 0x7F);
+        let package = crate::Package {
+            cmd: 0x12,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"unacked".to_vec(),
+        };
+
+        // No room left for the ACK
+        for _ in 0..LwPkt::CHANNEL_CAPACITY {
+            link.get_mut().write(package.clone()).unwrap();
+        }
+        let mut sequenced = package.clone();
+        sequenced.data.insert(0, 0x05);
+        raw_pkt
+            .write_all(&peer.encode(&sequenced).unwrap())
+            .unwrap();
+
+        assert_eq!(link.poll().unwrap(), vec![package.clone()]);
+        assert_eq!(link.take_send_error(), Some(crate::Error::ErrorMem));
+        assert_eq!(link.take_send_error(), None);
+
+        // The queued ACK goes out once there is room
+        raw_pkt.pending_bytes();
+        raw_pkt.clear();
+        assert_eq!(link.poll().unwrap(), vec![]);
+        assert_eq!(link.take_send_error(), None);
+
+        let mut ack = vec![0u8; raw_pkt.pending_bytes()];
+        raw_pkt.read_exact(&mut ack).unwrap();
+        let ack = crate::verify_frame(&ack).unwrap();
+        assert_eq!((ack.cmd, ack.data), (0x7F, vec![0x05]));
+
+        // Every sequence number is in flight, the oldest one is not overwritten
+        for seq in 0..=u8::MAX {
+            assert_eq!(link.send_reliable(package.clone()).unwrap().seq(), seq);
+            raw_pkt.pending_bytes();
+            raw_pkt.clear();
+        }
+        assert_eq!(
+            link.send_reliable(package).err(),
+            Some(crate::Error::SeqInFlight { seq: 0 })
+        );
+        assert_eq!(link.in_flight(), 256);
+    }
+
+    #[test]
+    fn moved_ring_buffer_test() {
+        let mut rb = LwRb::new(16);
+        rb.write(b"before");
+
+        let mut moved = vec![rb];
+        let mut rb = moved.pop().unwrap();
+        rb.write(b" after");
+
+        let mut out = [0u8; 12];
+        let len = unsafe {
+            crate::ffi::lwrb_read(
+                &mut rb.lwrb as *mut _,
+                out.as_mut_ptr() as *mut _,
+                out.len(),
+            )
+        };
+        assert_eq!(&out[..len], b"before after");
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn buffer_used_test() {
+        let mut rb = LwRb::new(16);
+        assert_eq!(rb.used(), 0);
+        rb.write(b"hello");
+        assert_eq!(rb.used(), 5);
+
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: b"drained".to_vec(),
+            })
+            .unwrap();
+        assert_eq!(lwpkt.write_buffer_used(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn from_raw_parts_test() {
+        // Stands in for a context set up on the C side
+        let mut rb = LwRb::new(1024);
+        let mut wb = LwRb::new(1024);
+        let mut context = Box::new(crate::ffi::lwpkt::default());
+        let res = unsafe {
+            crate::ffi::lwpkt_init(
+                &mut *context,
+                &mut wb.lwrb as *mut _,
+                &mut rb.lwrb as *mut _,
+            )
+        };
+        crate::check_result(res).unwrap();
+
+        let (mut lwpkt, raw_pkt) = unsafe { LwPkt::from_raw_parts(&mut *context) }.unwrap();
+        assert_eq!(lwpkt.read_buffer_size(), 1024);
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"adopted".to_vec(),
+        };
+        lwpkt.write(package.clone()).unwrap();
+
+        let frame = raw_pkt.from_pkt.try_recv().unwrap();
+        assert_eq!(crate::decode_all(&frame), vec![Ok(package)]);
+
+        drop(lwpkt);
+        assert_eq!(wb.used(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn pending_bytes_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert_eq!(raw_pkt.pending_bytes(), 0);
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"pending".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        lwpkt.write(package.clone()).unwrap();
+        lwpkt.write(package).unwrap();
+
+        let mut head = [0u8; 3];
+        raw_pkt.read_exact(&mut head).unwrap();
+        assert_eq!(raw_pkt.pending_bytes(), frame.len() * 2 - 3);
+
+        // Abort the rest, the next read starts on a fresh frame
+        raw_pkt.clear();
+        assert_eq!(raw_pkt.pending_bytes(), 0);
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x86,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![],
+            })
+            .unwrap();
+        let mut buffer = vec![];
+        raw_pkt.read_to_end(&mut buffer).unwrap();
+        assert_eq!(crate::decode_all(&buffer)[0].as_ref().unwrap().cmd, 0x86);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn close_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        let tap = raw_pkt.tee();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"last one".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        raw_pkt.write_all(&frame).unwrap();
+        lwpkt.write(package.clone()).unwrap();
+
+        raw_pkt.close();
+        assert!(!lwpkt.is_raw_connected());
+        assert_eq!(tap.try_read_chunk(), Err(crate::Error::ErrorClosedRaw));
+        assert_eq!(
+            raw_pkt.write(&frame).unwrap_err().kind(),
+            std::io::ErrorKind::BrokenPipe
+        );
+
+        assert_eq!(lwpkt.read(), Err(crate::Error::ErrorClosedRaw));
+        assert_eq!(lwpkt.write(package), Err(crate::Error::ErrorClosedRaw));
+
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        lwpkt.close();
+        assert!(!raw_pkt.is_pkt_connected());
+    }
+
+    #[test]
+    fn sequence_tracker_test() {
+        use crate::SequenceEvent;
+
+        let package = |from: u8, seq: u8| crate::Package {
+            cmd: 0x10,
+            from,
+            to: 0,
+            flags: 0,
+            data: vec![seq],
+        };
+        let mut tracker = crate::SequenceTracker::new(|p: &crate::Package| p.data.first().copied());
+
+        assert_eq!(tracker.track(&package(1, 254)), None);
+        assert_eq!(tracker.track(&package(1, 255)), None);
+        // Wraps around, and other senders are tracked on their own
+        assert_eq!(tracker.track(&package(1, 0)), None);
+        assert_eq!(tracker.track(&package(2, 7)), None);
+
+        assert_eq!(
+            tracker.track(&package(1, 3)),
+            Some(SequenceEvent::Gap {
+                from: 1,
+                expected: 1,
+                got: 3
+            })
+        );
+        assert_eq!(
+            tracker.track(&package(1, 2)),
+            Some(SequenceEvent::OutOfOrder {
+                from: 1,
+                expected: 4,
+                got: 2
+            })
+        );
+        assert_eq!(tracker.track(&package(1, 4)), None);
+        assert_eq!(tracker.track(&package(2, 8)), None);
+
+        let empty = crate::Package {
+            data: vec![],
+            ..package(1, 0)
+        };
+        assert_eq!(tracker.track(&empty), None);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn write_if_space_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(256), LwRb::new(512)).unwrap();
+        let package = crate::Package {
+            cmd: 0x42,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![1, 2, 3],
+        };
+
+        for _ in 0..LwPkt::CHANNEL_CAPACITY {
+            assert!(lwpkt.write_if_space(&package).unwrap());
+        }
+        assert!(!lwpkt.write_if_space(&package).unwrap());
+
+        let mut wire = vec![];
+        raw_pkt.read_to_end(&mut wire).unwrap();
+
+        let decoded = crate::decode_all(&wire);
+        assert_eq!(decoded.len(), LwPkt::CHANNEL_CAPACITY);
+        assert!(decoded.iter().all(|p| p.as_ref() == Ok(&package)));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn write_if_space_pool_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(256), LwRb::new(512)).unwrap();
+        let package = crate::Package {
+            cmd: 0x43,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![4, 5, 6],
+        };
+        let mut frame = vec![0u8; lwpkt.encode(&package).unwrap().len()];
+        assert!(lwpkt.pool().is_empty());
+
+        assert!(lwpkt.write_if_space(&package).unwrap());
+        raw_pkt.read_exact(&mut frame).unwrap();
+        assert_eq!(lwpkt.pool().len(), 1);
+
+        // The chunk read by the raw side carries the next frame, no new buffer is needed
+        for _ in 0..3 {
+            assert!(lwpkt.write_if_space(&package).unwrap());
+            assert!(lwpkt.pool().is_empty());
+
+            raw_pkt.read_exact(&mut frame).unwrap();
+            assert_eq!(lwpkt.pool().len(), 1);
+        }
+        assert_eq!(crate::verify_frame(&frame), Ok(package.clone()));
+
+        // A package that cannot be framed leaves the pooled buffer in the pool
+        lwpkt.set_max_data_len(2);
+        assert!(matches!(
+            lwpkt.write_if_space(&package),
+            Err(crate::Error::PayloadTooLarge { .. })
+        ));
+        assert_eq!(lwpkt.pool().len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn decode_slice_test() {
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(LwRb::new(256), LwRb::new(512)).unwrap();
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x33,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"in place".to_vec(),
+            })
+            .unwrap();
+
+        let mut decoded = vec![];
+        let consumed = lwpkt
+            .decode_slice(&frame, |package| decoded.push(package.clone()))
+            .unwrap();
+
+        assert_eq!(consumed, frame.len());
+        assert_eq!(decoded.len(), 1);
+        assert!(decoded[0].data_eq(b"in place"));
+        // Nothing left behind for the read methods
+        assert!(lwpkt.read().unwrap().is_empty());
+
+        // A bad frame in the middle does not hide the one after it
+        let mut bad = frame.clone();
+        *bad.last_mut().unwrap() ^= 0xFF;
+        let mut chunk = frame.clone();
+        chunk.extend(&bad);
+        chunk.extend(&frame);
+
+        decoded.clear();
+        let res = lwpkt.decode_slice(&chunk, |package| decoded.push(package.clone()));
+
+        assert_eq!(res, Err(crate::Error::ErrStop));
+        assert_eq!(decoded.len(), 2);
+        assert!(lwpkt.read().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn small_read_buffer_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(128), LwRb::new(1024)).unwrap();
+
+        let mut wire = vec![];
+        let mut sent = vec![];
+        for i in 0u32.. {
+            if wire.len() >= 10 * 1024 {
+                break;
+            }
+
+            let package = crate::Package {
+                cmd: i % 0x80,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![i as u8; (i % 100) as usize],
+            };
+            wire.extend(lwpkt.encode(&package).unwrap());
+            sent.push(package);
+        }
+
+        let mut decoded = vec![];
+        let consumed = lwpkt
+            .decode_slice(&wire, |package| decoded.push(package.clone()))
+            .unwrap();
+
+        assert_eq!(consumed, wire.len());
+        assert_eq!(decoded, sent);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn buffer_too_small_test() {
+        let min = crate::max_frame_len() + 1;
+
+        assert_eq!(
+            LwPkt::new(LwRb::new(16), LwRb::new(min - 1)).unwrap_err(),
+            crate::Error::BufferTooSmall { size: min - 1, min }
+        );
+        assert_eq!(
+            LwPkt::new_loopback(LwRb::new(16), LwRb::new(min - 1)).unwrap_err(),
+            crate::Error::BufferTooSmall { size: min - 1, min }
+        );
+
+        // Exactly one max sized frame fits
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(LwRb::new(16), LwRb::new(min)).unwrap();
+        let package = crate::Package {
+            cmd: 0x7F,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![0xFF; LwPkt::MAX_PACKAGE_SIZE as usize],
+        };
+        assert!(lwpkt.encode(&package).unwrap().len() <= crate::max_frame_len());
+    }
+
+    #[test]
+    fn try_from_code_test() {
+        use crate::Error;
+
+        assert_eq!(Error::try_from_code(crate::ffi::lwpktr_t::lwpktOK), None);
+        assert_eq!(
+            Error::try_from_code(crate::ffi::lwpktr_t::lwpktERRCRC),
+            Some(Error::ErrorCRC)
+        );
+        assert_eq!(Error::try_from_code(200), Some(Error::Unknown(200)));
+        assert_eq!(Error::from(200), Error::Unknown(200));
+        assert_eq!(crate::check_result(200), Err(Error::Unknown(200)));
+        assert_eq!(crate::check_result(crate::ffi::lwpktr_t::lwpktOK), Ok(()));
+    }
+
+    #[test]
+    fn error_all_test() {
+        use crate::Error;
+
+        let all = Error::all();
+
+        // Fails to build when a variant is added without listing it in `Error::all`
+        let index = |e: &Error| match e {
+            Error::ERR => 0,
+            Error::InProgress => 1,
+            Error::Valid => 2,
+            Error::ErrorCRC => 3,
+            Error::ErrStop => 4,
+            Error::WaitData => 5,
+            Error::ErrorMem => 6,
+            Error::ErrorClosedRaw => 7,
+            Error::PayloadTooLarge { .. } => 8,
+            Error::CmdOutOfRange { .. } => 9,
+            Error::Io { .. } => 10,
+            Error::AckTimeout { .. } => 11,
+            Error::FrameMismatch { .. } => 12,
+            Error::BufferTooSmall { .. } => 13,
+            Error::FromMismatch { .. } => 14,
+            Error::AckCmdReserved { .. } => 15,
+            Error::SeqInFlight { .. } => 16,
+            Error::Unknown(_) => 17,
+        };
+
+        assert_eq!(all.len(), 18);
+        for (i, e) in all.iter().enumerate() {
+            assert_eq!(index(e), i);
+            assert!(!e.to_string().is_empty());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn read_timed_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+
+        let before = std::time::Instant::now();
+        for cmd in [0x21, 0x22] {
+            let frame = lwpkt
+                .encode(&crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![cmd as u8],
+                })
+                .unwrap();
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        let packets = lwpkt.read_timed().unwrap();
+        let after = std::time::Instant::now();
+
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[0].1.cmd, 0x21);
+        assert!(packets[0].0 >= before && packets[0].0 <= packets[1].0);
+        assert!(packets[1].0 <= after);
+        assert!(lwpkt.read_timed().unwrap().is_empty());
+    }
+
+    #[test]
+    fn find_test() {
+        let mut rb = LwRb::new(16);
+        // Move the read position so the contents wrap around the end of the memory
+        rb.write(b"0123456789");
+        let mut skipped = [0u8; 10];
+        unsafe {
+            crate::ffi::lwrb_read(&mut rb.lwrb as *mut _, skipped.as_mut_ptr() as *mut _, 10)
+        };
+        rb.write(b"hello world");
+
+        assert_eq!(rb.find(b"world", 0), Some(6));
+        assert_eq!(rb.find(b"o", 0), Some(4));
+        assert_eq!(rb.find(b"o", 5), Some(7));
+        assert_eq!(rb.find(b"xyz", 0), None);
+        assert_eq!(rb.used(), 11);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn can_write_test() {
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(LwRb::new(16), LwRb::new(512)).unwrap();
+        let max = LwPkt::MAX_PACKAGE_SIZE as usize;
+
+        assert!(lwpkt.can_write(0));
+        assert!(lwpkt.can_write(max));
+        assert!(!lwpkt.can_write(max + 1));
+
+        lwpkt.set_max_data_len(8);
+        assert!(lwpkt.can_write(8));
+        assert!(!lwpkt.can_write(9));
+
+        // The frame length used matches what goes on the wire
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x01,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![0; 8],
+            })
+            .unwrap();
+        assert!(frame.len() <= crate::frame_len(8));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn send_test() {
+        let (lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let lwpkt = std::sync::Arc::new(std::sync::Mutex::new(lwpkt));
+
+        let writer = lwpkt.clone();
+        std::thread::spawn(move || {
+            writer
+                .lock()
+                .unwrap()
+                .write(crate::Package {
+                    cmd: 0x61,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: b"from a thread".to_vec(),
+                })
+                .unwrap();
+        })
+        .join()
+        .unwrap();
+
+        let mut wire = vec![];
+        raw_pkt.read_to_end(&mut wire).unwrap();
+        raw_pkt.write_all(&wire).unwrap();
+
+        let packets = lwpkt.lock().unwrap().read().unwrap();
+        assert!(packets[0].data_eq(b"from a thread"));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn max_packets_per_read_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::builder().max_packets_per_read(2).build().unwrap();
+
+        for cmd in 1..=5 {
+            let frame = lwpkt
+                .encode(&crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![],
+                })
+                .unwrap();
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        let cmds = |packets: Vec<crate::Package>| packets.iter().map(|p| p.cmd).collect::<Vec<_>>();
+        assert_eq!(cmds(lwpkt.read().unwrap()), vec![1, 2]);
+        // Chunks past the cap were left in the channel
+        assert_eq!(
+            raw_pkt.pkt_channel_capacity_remaining(),
+            LwPkt::CHANNEL_CAPACITY - 3
+        );
+        assert_eq!(cmds(lwpkt.read().unwrap()), vec![3, 4]);
+        assert_eq!(cmds(lwpkt.read().unwrap()), vec![5]);
+
+        lwpkt.set_max_packets_per_read(None);
+        assert!(lwpkt.read().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn channel_capacity_remaining_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        assert_eq!(
+            lwpkt.raw_channel_capacity_remaining(),
+            LwPkt::CHANNEL_CAPACITY
+        );
+        assert_eq!(
+            raw_pkt.pkt_channel_capacity_remaining(),
+            LwPkt::CHANNEL_CAPACITY
+        );
+
+        let package = crate::Package {
+            cmd: 0x01,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![],
+        };
+        lwpkt.write(package.clone()).unwrap();
+        raw_pkt.write_all(&[0x00]).unwrap();
+        assert_eq!(
+            lwpkt.raw_channel_capacity_remaining(),
+            LwPkt::CHANNEL_CAPACITY - 1
+        );
+        assert_eq!(
+            raw_pkt.pkt_channel_capacity_remaining(),
+            LwPkt::CHANNEL_CAPACITY - 1
+        );
+
+        while lwpkt.raw_channel_capacity_remaining() > 0 {
+            lwpkt.write(package.clone()).unwrap();
+        }
+        assert_eq!(lwpkt.write(package), Err(crate::Error::ErrorMem));
+    }
+
+    #[test]
+    fn tx_hooks_test() {
+        let log = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+
+        let mut connection =
+            crate::Connection::with_sizes(std::collections::VecDeque::new(), 1024, 1024).unwrap();
+        let pre = log.clone();
+        connection.set_pre_tx(move || pre.lock().unwrap().push("pre"));
+        let post = log.clone();
+        connection.set_post_tx(move || post.lock().unwrap().push("post"));
+
+        connection
+            .send(crate::Package {
+                cmd: 0x01,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![1, 2, 3],
+            })
+            .unwrap();
+        assert_eq!(*log.lock().unwrap(), vec!["pre", "post"]);
+
+        // Rejected before anything is written, the line is left alone
+        let too_large = crate::Package {
+            cmd: 0x01,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![0; LwPkt::MAX_PACKAGE_SIZE as usize + 1],
+        };
+        assert!(connection.send(too_large).is_err());
+        assert_eq!(log.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn filter_by_address_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::builder()
+            .address(0x22)
+            .filter_by_address(true)
+            .build()
+            .unwrap();
+
+        for to in [0x22, 0x33, crate::BROADCAST_ADDRESS, 0x44] {
+            let frame = lwpkt
+                .encode(&crate::Package {
+                    cmd: 0x01,
+                    from: 0,
+                    to,
+                    flags: 0,
+                    data: vec![],
+                })
+                .unwrap();
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        let to = lwpkt
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| p.to)
+            .collect::<Vec<_>>();
+        assert_eq!(to, vec![0x22, crate::BROADCAST_ADDRESS]);
+        assert_eq!(lwpkt.filtered_count(), 2);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", feature = "testing", not(feature = "no-crc")))]
+    fn inject_garbage_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let package = crate::Package {
+            cmd: 0x55,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"recovered".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+
+        // Flipped bit in the header, only the CRC catches it
+        let mut corrupted = frame.clone();
+        corrupted[3] ^= 0x01;
+        raw_pkt.inject_garbage(&[0x00, 0x13, 0x37]).unwrap();
+        raw_pkt.inject_garbage(&corrupted).unwrap();
+        assert_eq!(lwpkt.read(), Err(crate::Error::ErrorCRC));
+
+        raw_pkt.write_all(&frame).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![package]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn read_pooled_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x11,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![7; 64],
+            })
+            .unwrap();
+
+        raw_pkt.write_all(&frame).unwrap();
+        let packets = lwpkt.read_pooled().unwrap();
+        assert!(packets[0].data_eq(&[7; 64]));
+        let buffer = packets[0].data.as_ptr();
+        assert!(lwpkt.pool().is_empty());
+
+        drop(packets);
+        assert_eq!(lwpkt.pool().len(), 1);
+
+        // The same allocation carries the next payload
+        raw_pkt.write_all(&frame).unwrap();
+        let packets = lwpkt.read_pooled().unwrap();
+        assert_eq!(packets[0].data.as_ptr(), buffer);
+        assert!(lwpkt.pool().is_empty());
+
+        let package = packets.into_iter().next().unwrap().into_package();
+        assert!(package.data_eq(&[7; 64]));
+        assert!(lwpkt.pool().is_empty());
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-crc")))]
+    fn single_chunk_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(512)).unwrap();
+
+        let mut chunk = vec![];
+        for i in 0..40u8 {
+            let frame = lwpkt
+                .encode(&crate::Package {
+                    cmd: 0x40,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![i],
+                })
+                .unwrap();
+            chunk.extend_from_slice(&frame);
+        }
+
+        let mut bad = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x41,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"bad".to_vec(),
+            })
+            .unwrap();
+        let crc = bad.len() - 2;
+        bad[crc] ^= 0xFF;
+        chunk.extend_from_slice(&bad);
+
+        let mut decoded = vec![];
+        let res = lwpkt.decode_slice(&chunk, |package| decoded.push(package.data.clone()));
+
+        assert_eq!(res, Err(crate::Error::ErrorCRC));
+        assert_eq!(decoded, (0..40u8).map(|i| vec![i]).collect::<Vec<_>>());
+        // The copy of the read buffer stays in step over all the frames
+        assert_eq!(lwpkt.last_bad_frame(), Some(&bad[..bad.len() - 1]));
+
+        // The chunk is written once, then one `lwpkt_read` per frame (the bad one included)
+        // and a last one skipping the stop byte of the bad frame. The read buffer is never
+        // peeked, it used to be before each of these 42 `lwpkt_read` calls.
+        let calls = lwpkt.ffi_calls;
+        assert_eq!(calls.lwrb_write, 1);
+        assert_eq!(calls.lwpkt_read, 42);
+        assert_eq!(calls.lwrb_peek, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn next_packet_len_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert_eq!(lwpkt.next_packet_len(), None);
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x12,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![0xA5; 20],
+            })
+            .unwrap();
+        let header = frame.len() - 20 - usize::from(crate::config().use_crc) - 1;
+
+        lwpkt.feed(&frame[..header - 1]).unwrap();
+        assert_eq!(lwpkt.next_packet_len(), None);
+
+        lwpkt.feed(&frame[header - 1..header + 4]).unwrap();
+        assert_eq!(lwpkt.next_packet_len(), Some(20));
+
+        let next = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x12,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![1, 2, 3],
+            })
+            .unwrap();
+        lwpkt.feed(&frame[header + 4..]).unwrap();
+        lwpkt.feed(&next).unwrap();
+        assert_eq!(lwpkt.next_packet_len(), Some(20));
+
+        // The second packet stays queued behind the cap
+        lwpkt.set_max_packets_per_read(Some(1));
+        let packets = lwpkt.read().unwrap();
+        assert_eq!(packets[0].data.len(), 20);
+        assert_eq!(lwpkt.next_packet_len(), Some(3));
+    }
+
+    #[test]
+    #[cfg(feature = "sync")]
+    fn sync_transport_test() {
+        use std::io::{Read, Write};
+
+        let (transport, mut raw) = crate::SyncTransport::pair();
+        let mut lwpkt = LwPkt::with_transport(LwRb::new(1024), LwRb::new(1024), transport).unwrap();
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x21,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"no async".to_vec(),
+            })
+            .unwrap();
+
+        let mut frame = [0u8; 64];
+        let len = raw.read(&mut frame).unwrap();
+        assert!(len > 0);
+        assert_eq!(raw.read(&mut frame[len..]).unwrap(), 0);
+
+        // Echoed back in two chunks
+        raw.write_all(&frame[..3]).unwrap();
+        raw.write_all(&frame[3..len]).unwrap();
+        let packets = lwpkt.read().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].data_eq(b"no async"));
+
+        drop(raw);
+        assert_eq!(lwpkt.read(), Err(crate::Error::ErrorClosedRaw));
+    }
+
+    // Built with `--no-default-features --features sync`, async-channel is not compiled in
+    #[test]
+    #[cfg(not(feature = "async"))]
+    fn sync_default_transport_test() {
+        use std::io::{Read, Write};
+
+        let package = crate::Package {
+            cmd: 0x22,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"sync only".to_vec(),
+        };
+
+        // A plain `LwPkt` runs over a `SyncTransport`
+        let (transport, mut raw) = crate::SyncTransport::pair();
+        let mut lwpkt: LwPkt =
+            LwPkt::with_transport(LwRb::new(1024), LwRb::new(1024), transport).unwrap();
+        lwpkt.write(package.clone()).unwrap();
+
+        let mut wire = vec![];
+        raw.read_to_end(&mut wire).unwrap();
+        assert_eq!(crate::decode_all(&wire), vec![Ok(package.clone())]);
+        assert_eq!(crate::verify_frame(&wire), Ok(package.clone()));
+
+        raw.write_all(&wire).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![package.clone()]);
+
+        let mut connection =
+            crate::Connection::with_sizes(std::collections::VecDeque::new(), 1024, 1024).unwrap();
+        connection.send(package.clone()).unwrap();
+        assert_eq!(connection.poll(), Ok(1));
+        assert_eq!(connection.recv(), Some(package));
+    }
+
+    #[test]
+    fn usable_capacity_test() {
+        let mut rb = LwRb::with_usable_capacity(8);
+        assert_eq!(rb.capacity(), 8);
+        assert_eq!(rb.size(), 9);
+        assert_eq!(rb.write(b"0123456789"), 8);
+
+        assert_eq!(LwRb::new(8).capacity(), 7);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-crc")))]
+    fn decode_trace_test() {
+        use crate::DecodeEvent;
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        lwpkt.set_decode_trace(move |event| tx.send(event).unwrap());
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x21,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"trace".to_vec(),
+            })
+            .unwrap();
+
+        lwpkt.feed(&frame).unwrap();
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            [
+                DecodeEvent::StartByte,
+                DecodeEvent::Length(5),
+                DecodeEvent::Packet { cmd: 0x21, len: 5 },
+            ]
+        );
+
+        let mut bad_crc = frame.clone();
+        let crc = bad_crc.len() - 2;
+        bad_crc[crc] ^= 0xFF;
+        assert_eq!(lwpkt.feed(&bad_crc), Err(crate::Error::ErrorCRC));
+        assert_eq!(
+            rx.try_iter().last(),
+            Some(DecodeEvent::CrcMismatch {
+                expected: frame[crc],
+                got: frame[crc] ^ 0xFF,
+            })
+        );
+
+        let mut bad_stop = frame.clone();
+        let stop = bad_stop.len() - 1;
+        bad_stop[stop] = 0x00;
+        assert_eq!(lwpkt.feed(&bad_stop), Err(crate::Error::ErrStop));
+        assert_eq!(
+            rx.try_iter().last(),
+            Some(DecodeEvent::StopByteMismatch { got: 0x00 })
+        );
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn package_builder_test() {
+        let package = crate::Package::builder()
+            .cmd(0x42)
+            .to(0x22)
+            .data(b"built")
+            .build();
+        assert_eq!(
+            package,
             crate::Package {
+                cmd: 0x42,
+                from: 0,
+                to: 0x22,
+                flags: 0,
+                data: b"built".to_vec(),
+            }
+        );
+
+        // `from` on the wire is the address of the sender
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        lwpkt.set_addres(0x11).unwrap();
+        lwpkt.write(package).unwrap();
+
+        let packets = lwpkt.read().unwrap();
+        assert_eq!(packets[0].from, 0x11);
+        assert_eq!(packets[0].to, 0x22);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn empty_chunk_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        raw_pkt.write_owned(Vec::new()).unwrap();
+        assert_eq!(lwpkt.read_outcome(), Ok(crate::ReadOutcome::Idle));
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x18,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"empty".to_vec(),
+            })
+            .unwrap();
+        for chunk in frame.chunks(3) {
+            raw_pkt.write_owned(Vec::new()).unwrap();
+            raw_pkt.write_owned(chunk.to_vec()).unwrap();
+        }
+        raw_pkt.write_owned(Vec::new()).unwrap();
+
+        let packets = lwpkt.read().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert!(packets[0].data_eq(b"empty"));
+        assert_eq!(lwpkt.last_status(), crate::Error::Valid);
+    }
+
+    #[test]
+    fn library_version_test() {
+        let (lwpkt, lwrb) = crate::library_version();
+        assert!(!lwpkt.is_empty());
+        assert!(!lwrb.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn loopback_roundtrip_test() {
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(LwRb::new(16), LwRb::new(512)).unwrap();
+        let package = crate::Package {
+            cmd: 0x3C,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"power-on self test".to_vec(),
+        };
+
+        let decoded = lwpkt.loopback_roundtrip(package.clone()).unwrap();
+        assert_eq!(decoded, package);
+        assert!(lwpkt.read().unwrap().is_empty());
+
+        // A received frame half decoded is left alone
+        let frame = lwpkt.encode(&package).unwrap();
+        lwpkt.feed(&frame[..4]).unwrap();
+        assert_eq!(
+            lwpkt.loopback_roundtrip(package),
+            Err(crate::Error::InProgress)
+        );
+        lwpkt.feed(&frame[4..]).unwrap();
+        assert_eq!(lwpkt.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn strict_from_test() {
+        let (mut lwpkt, _raw_pkt) = LwPkt::builder()
+            .address(0x11)
+            .strict_from(true)
+            .build()
+            .unwrap();
+        let mut package = crate::Package {
+            cmd: 0x42,
+            from: 0x33,
+            to: 0x22,
+            flags: 0,
+            data: b"relayed".to_vec(),
+        };
+
+        assert_eq!(
+            lwpkt.write(package.clone()),
+            Err(crate::Error::FromMismatch {
+                from: 0x33,
+                address: 0x11,
+            })
+        );
+
+        package.from = 0;
+        lwpkt.write(package.clone()).unwrap();
+        package.from = 0x11;
+        lwpkt.write(package.clone()).unwrap();
+
+        lwpkt.set_strict_from(false);
+        package.from = 0x33;
+        lwpkt.write(package).unwrap();
+    }
+
+    #[test]
+    fn split_test() {
+        let (mut producer, mut consumer) = LwRb::new(8).split();
+        assert_eq!(producer.free(), 7);
+
+        assert_eq!(producer.write(b"0123456789"), 7);
+        assert_eq!(consumer.used(), 7);
+
+        let mut buf = [0u8; 4];
+        assert_eq!(consumer.read(&mut buf), 4);
+        assert_eq!(&buf, b"0123");
+        assert_eq!(producer.write(b"789"), 3);
+
+        let mut buf = [0u8; 8];
+        assert_eq!(consumer.read(&mut buf), 6);
+        assert_eq!(&buf[..6], b"456789");
+        assert_eq!(consumer.used(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "atomic")]
+    fn split_threads_test() {
+        let (mut producer, mut consumer) = LwRb::new(16).split();
+        let data = (0..=255u8).cycle().take(4096).collect::<Vec<_>>();
+
+        let expected = data.clone();
+        let writer = std::thread::spawn(move || {
+            let mut from = 0;
+            while from < data.len() {
+                from += producer.write(&data[from..]);
+                std::thread::yield_now();
+            }
+        });
+
+        let mut received = vec![];
+        let mut buf = [0u8; 16];
+        while received.len() < expected.len() {
+            let len = consumer.read(&mut buf);
+            received.extend_from_slice(&buf[..len]);
+            std::thread::yield_now();
+        }
+        writer.join().unwrap();
+
+        assert_eq!(received, expected);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn count_packets_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        for i in 0..5u8 {
+            lwpkt
+                .write(crate::Package {
+                    cmd: 0x10,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![i; 8],
+                })
+                .unwrap();
+        }
+
+        assert_eq!(lwpkt.count_packets(), Ok(5));
+        // A single payload buffer went round for every packet
+        assert_eq!(lwpkt.pool().len(), 1);
+        assert_eq!(lwpkt.count_packets(), Ok(0));
+        assert!(lwpkt.read().unwrap().is_empty());
+    }
+
+    #[test]
+    fn write_overwrite_test() {
+        let read_all = |rb: LwRb| {
+            let (_, mut consumer) = rb.split();
+            let mut buf = [0u8; 16];
+            let len = consumer.read(&mut buf);
+            buf[..len].to_vec()
+        };
+
+        let mut rb = LwRb::new(8);
+        assert_eq!(rb.write(b"0123456"), 7);
+        rb.write_overwrite(b"ab");
+        assert_eq!(rb.used(), 7);
+        // The two oldest bytes made room for the new ones
+        assert_eq!(read_all(rb), b"23456ab");
+
+        let mut rb = LwRb::new(8);
+        rb.write(b"012");
+        rb.write_overwrite(b"ABCDEFGHIJ");
+        assert_eq!(read_all(rb), b"DEFGHIJ");
+    }
+
+    #[test]
+    #[cfg(all(feature = "tokio-codec", not(feature = "no-crc")))]
+    fn codec_bad_frame_test() {
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = crate::LwPktCodec::with_sizes(1024, 1024).unwrap();
+        let package = |data: &[u8]| crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: data.to_vec(),
+        };
+
+        let mut buffer = bytes::BytesMut::new();
+        codec.encode(package(b"before"), &mut buffer).unwrap();
+        let mut bad = bytes::BytesMut::new();
+        codec.encode(package(b"corrupted"), &mut bad).unwrap();
+        let crc = bad.len() - 2;
+        bad[crc] ^= 0xFF;
+        buffer.extend_from_slice(&bad);
+        codec.encode(package(b"after"), &mut buffer).unwrap();
+
+        // Both good packets come out before the error
+        assert!(
+            codec
+                .decode(&mut buffer)
+                .unwrap()
+                .unwrap()
+                .data_eq(b"before")
+        );
+        assert!(
+            codec
+                .decode(&mut buffer)
+                .unwrap()
+                .unwrap()
+                .data_eq(b"after")
+        );
+        let e = codec.decode(&mut buffer).unwrap_err();
+        assert_eq!(e.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn into_stream_test() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let (lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let (mut encoder, _encoder_raw) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let first = crate::Package {
+            cmd: 0x21,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"first".to_vec(),
+        };
+        let second = crate::Package {
+            cmd: 0x22,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"second".to_vec(),
+        };
+
+        // Both frames arrive in a single chunk
+        let mut chunk = encoder.encode(&first).unwrap();
+        chunk.extend(encoder.encode(&second).unwrap());
+        raw_pkt.write_owned(chunk).unwrap();
+
+        let mut stream = std::pin::pin!(lwpkt.into_stream());
+        assert_eq!(stream.next().now_or_never(), Some(Some(Ok(first))));
+        // Already decoded, no more bytes are awaited for it
+        assert_eq!(stream.next().now_or_never(), Some(Some(Ok(second))));
+        assert_eq!(stream.next().now_or_never(), None);
+
+        drop(raw_pkt);
+        assert_eq!(stream.next().now_or_never(), Some(None));
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn sink_backpressure_test() {
+        use futures_sink::Sink;
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let package = crate::Package {
+            cmd: 0x23,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"sunk".to_vec(),
+        };
+        for _ in 0..LwPkt::CHANNEL_CAPACITY {
+            lwpkt.write(package.clone()).unwrap();
+        }
+
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        let mut packets = lwpkt.into_sink();
+        let mut sink = std::pin::Pin::new(&mut packets);
+
+        assert_eq!(
+            sink.as_mut().poll_ready(&mut cx),
+            std::task::Poll::Ready(Ok(()))
+        );
+        sink.as_mut().start_send(package.clone()).unwrap();
+        // The raw channel is full, the packet waits for room
+        assert!(sink.as_mut().poll_ready(&mut cx).is_pending());
+        assert!(sink.as_mut().poll_flush(&mut cx).is_pending());
+
+        let mut buffer = [0u8; 1];
+        raw_pkt.read_exact(&mut buffer).unwrap();
+        assert_eq!(
+            sink.as_mut().poll_ready(&mut cx),
+            std::task::Poll::Ready(Ok(()))
+        );
+        // Sent in place of the chunk just read
+        assert_eq!(packets.into_inner().raw_channel_capacity_remaining(), 0);
+    }
+
+    #[cfg(feature = "futures")]
+    #[test]
+    fn sink_forward_test() {
+        use futures_util::{FutureExt, StreamExt};
+
+        let (lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let packages: Vec<_> = (0..3u8)
+            .map(|i| crate::Package {
+                cmd: 0x24,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![i; 4],
+            })
+            .collect();
+
+        let forwarded = futures_util::stream::iter(packages.clone().into_iter().map(Ok))
+            .forward(lwpkt.into_sink());
+        assert_eq!(forwarded.now_or_never(), Some(Ok(())));
+
+        let mut bytes = vec![];
+        while let Some(Ok(chunk)) = raw_pkt.read_chunk().now_or_never() {
+            bytes.extend(chunk);
+        }
+        let decoded: Vec<_> = crate::decode_all(&bytes)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(decoded, packages);
+        // forward closes the sink once the stream ends
+        assert!(raw_pkt.read_chunk().now_or_never().unwrap().is_err());
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn swap_address_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::builder()
+            .address(0x11)
+            .filter_by_address(true)
+            .build()
+            .unwrap();
+
+        assert_eq!(lwpkt.swap_address(0x22), Ok(0x11));
+        assert_eq!(lwpkt.address(), 0x22);
+
+        for to in [0x11, 0x22] {
+            let frame = lwpkt
+                .encode(&crate::Package {
+                    cmd: 0x02,
+                    from: 0,
+                    to,
+                    flags: 0,
+                    data: vec![],
+                })
+                .unwrap();
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        // Only the new address passes the filter and is put on the wire as `from`
+        let packages = lwpkt.read().unwrap();
+        assert_eq!(packages.len(), 1);
+        assert_eq!((packages[0].from, packages[0].to), (0x22, 0x22));
+        assert_eq!(lwpkt.filtered_count(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn drain_channels_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let package = crate::Package {
+            cmd: 0x03,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"drained".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        let (head, tail) = frame.split_at(frame.len() / 2);
+
+        raw_pkt.write_all(head).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![]);
+
+        // Queued in both directions, none of it survives
+        raw_pkt.write_all(&frame).unwrap();
+        lwpkt.write(package.clone()).unwrap();
+        lwpkt.drain_channels();
+        assert_eq!(raw_pkt.pending_bytes(), 0);
+
+        // The half decoded frame is still there
+        raw_pkt.write_all(tail).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![package]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn read_from_test() {
+        let (mut lwpkt, _raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let first = crate::Package {
+            cmd: 0x04,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"first".to_vec(),
+        };
+        let second = crate::Package {
+            cmd: 0x05,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"second".to_vec(),
+        };
+        let first_frame = lwpkt.encode(&first).unwrap();
+        let mut bytes = first_frame.clone();
+        bytes.extend(lwpkt.encode(&second).unwrap());
+        let mut src = std::io::Cursor::new(bytes);
+
+        // Stops right after the first frame
+        assert_eq!(
+            lwpkt.read_from(&mut src, first_frame.len()).unwrap(),
+            vec![first]
+        );
+        assert_eq!(src.position(), first_frame.len() as u64);
+
+        assert_eq!(lwpkt.read_from(&mut src, 1).unwrap(), vec![]);
+        assert_eq!(lwpkt.read_from(&mut src, 1024).unwrap(), vec![second]);
+        assert_eq!(lwpkt.read_from(&mut src, 1024).unwrap(), vec![]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn write_to_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let package = crate::Package {
+            cmd: 0x06,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"written".to_vec(),
+        };
+
+        let mut dst = vec![];
+        lwpkt.write_to(&mut dst, package.clone()).unwrap();
+        lwpkt.write_to(&mut dst, package.clone()).unwrap();
+        // Nothing goes through the raw channel
+        assert_eq!(raw_pkt.pending_bytes(), 0);
+
+        let decoded = crate::decode_all(&dst);
+        assert_eq!(decoded, vec![Ok(package.clone()), Ok(package)]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn set_on_packet_test() {
+        use std::sync::Arc;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        lwpkt.set_on_packet(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x22,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"counted".to_vec(),
+            })
+            .unwrap();
+        raw_pkt.write_all(&frame.repeat(3)).unwrap();
+
+        assert_eq!(lwpkt.read().unwrap().len(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Nothing new decoded, nothing reported
+        assert_eq!(lwpkt.read().unwrap().len(), 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        raw_pkt.write_all(&frame).unwrap();
+        assert_eq!(lwpkt.read().unwrap().len(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn flags_roundtrip_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        for flags in [0, 0x7F, 0x80, u32::MAX] {
+            let package = crate::Package::builder()
+                .cmd(0x31)
+                .flags(flags)
+                .data(b"flagged".to_vec())
+                .build();
+            // Ignored on write and `0` on read without the `flags` feature
+            let expected = if cfg!(feature = "flags") { flags } else { 0 };
+
+            let frame = lwpkt.encode(&package).unwrap();
+            assert_eq!(crate::verify_frame(&frame).unwrap().flags, expected);
+
+            lwpkt.write(package).unwrap();
+            let decoded = lwpkt.read().unwrap();
+            assert_eq!(decoded.len(), 1);
+            assert_eq!(decoded[0].flags, expected);
+            assert_eq!(lwpkt.current().unwrap().flags(), expected);
+        }
+    }
+
+    #[test]
+    fn raw_transport_test() {
+        // Synchronous point-to-point wire, every frame sent is the next chunk received
+        #[derive(Default)]
+        struct Wire(std::collections::VecDeque<Vec<u8>>);
+
+        impl crate::RawTransport for Wire {
+            fn send_bytes(&mut self, frame: Vec<u8>) -> Result<crate::Sent, crate::Error> {
+                self.0.push_back(frame);
+                Ok(crate::Sent::All)
+            }
+
+            fn recv_bytes(&mut self) -> Result<Option<Vec<u8>>, crate::Error> {
+                Ok(self.0.pop_front())
+            }
+        }
+
+        let min = crate::max_frame_len() + 1;
+        assert_eq!(
+            LwPkt::with_transport(LwRb::new(16), LwRb::new(min - 1), Wire::default()).err(),
+            Some(crate::Error::BufferTooSmall { size: min - 1, min })
+        );
+
+        let mut lwpkt =
+            LwPkt::with_transport(LwRb::new(256), LwRb::new(1024), Wire::default()).unwrap();
+
+        for cmd in [0x10, 0x11] {
+            lwpkt
+                .write(crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![cmd as u8; 4],
+                })
+                .unwrap();
+        }
+
+        let packets = lwpkt.read().unwrap();
+        assert_eq!(packets.len(), 2);
+        assert_eq!(packets[1].cmd, 0x11);
+        assert!(packets[1].data_eq(&[0x11; 4]));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn debug_test() {
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(512), LwRb::new(512)).unwrap();
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![],
+            })
+            .unwrap();
+
+        let debug = format!("{lwpkt:?}");
+        assert!(debug.contains("read_buffer_size: 512"));
+        assert!(debug.contains("to_raw: 1"));
+
+        let debug = format!("{raw_pkt:?}");
+        assert!(debug.contains("from_pkt: 1"));
+        assert!(debug.contains("last_read: 0"));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn on_packet_test() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (mut lwpkt, mut raw_pkt) = LwPkt::builder()
+            .on_packet(move |package| tx.send(package.cmd).unwrap())
+            .build()
+            .unwrap();
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x21,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"event".to_vec(),
+            })
+            .unwrap();
+        raw_pkt.write_all(&frame).unwrap();
+
+        assert_eq!(lwpkt.read().unwrap().len(), 1);
+        assert_eq!(rx.try_recv(), Ok(0x21));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn last_status_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert_eq!(lwpkt.last_status(), crate::Error::WaitData);
+
+        let mut frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"status".to_vec(),
+            })
+            .unwrap();
+
+        lwpkt.feed(&frame[..3]).unwrap();
+        assert_eq!(lwpkt.last_status(), crate::Error::InProgress);
+
+        lwpkt.feed(&frame[3..]).unwrap();
+        assert_eq!(lwpkt.last_status(), crate::Error::Valid);
+
+        let stop = frame.len() - 1;
+        frame[stop] = 0x00;
+        assert!(lwpkt.feed(&frame).is_err());
+        assert_eq!(lwpkt.last_status(), crate::Error::ErrStop);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-crc")))]
+    fn last_bad_frame_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert_eq!(lwpkt.last_bad_frame(), None);
+
+        let mut frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"line noise".to_vec(),
+            })
+            .unwrap();
+        let crc = frame.len() - 2;
+        frame[crc] ^= 0xFF;
+
+        // Noise before the frame and the frame split over two writes
+        lwpkt.feed(&[0x00, 0x13]).unwrap();
+        lwpkt.feed(&frame[..5]).unwrap();
+        assert_eq!(lwpkt.feed(&frame[5..]), Err(crate::Error::ErrorCRC));
+
+        assert_eq!(lwpkt.last_bad_frame(), Some(&frame[..frame.len() - 1]));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn resync_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+        assert_eq!(lwpkt.resync(), Ok(0));
+
+        let truncated = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![0x55; 64],
+            })
+            .unwrap();
+        let package = crate::Package {
+            cmd: 0x86,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"aligned".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+
+        // The head of a frame whose tail got lost, the next frame is taken as its payload
+        raw_pkt.write_all(&truncated[..6]).unwrap();
+        raw_pkt.write_all(&frame).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![]);
+        assert!(lwpkt.bytes_needed().is_some());
+
+        assert_eq!(lwpkt.resync(), Ok(6));
+        assert_eq!(lwpkt.bytes_needed(), None);
+        assert_eq!(lwpkt.read().unwrap(), vec![package.clone()]);
+
+        // Decoding goes on past a bad stop byte, the frame after the noise is not held back
+        let mut corrupted = frame.clone();
+        *corrupted.last_mut().unwrap() ^= 0xFF;
+        let mut chunk = corrupted;
+        chunk.extend([0x01, 0x02, 0x03]);
+        chunk.extend(&frame);
+        raw_pkt.write_all(&chunk).unwrap();
+        assert_eq!(lwpkt.read(), Err(crate::Error::ErrStop));
+
+        assert_eq!(lwpkt.resync(), Ok(0));
+        assert_eq!(lwpkt.read().unwrap(), vec![package]);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn write_owned_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: b"owned".to_vec(),
+            })
+            .unwrap();
+
+        #[cfg(feature = "bytes")]
+        raw_pkt.write_bytes(bytes::Bytes::from(frame)).unwrap();
+        #[cfg(not(feature = "bytes"))]
+        raw_pkt.write_owned(frame).unwrap();
+
+        let s = lwpkt.read().unwrap();
+        assert_eq!(s.len(), 1);
+        assert!(s[0].data_eq(b"owned"));
+
+        drop(lwpkt);
+        assert_eq!(
+            raw_pkt.write_owned(vec![0xAA]),
+            Err(crate::Error::ErrorClosedRaw)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn rebuild_channels_test() {
+        let (mut lwpkt, mut old_raw) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"moved".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        lwpkt.write(package.clone()).unwrap();
+        old_raw.write_all(&frame).unwrap();
+
+        let mut raw_pkt = lwpkt.rebuild_channels(2);
+        assert!(!old_raw.is_pkt_connected());
+
+        assert_eq!(raw_pkt.from_pkt.try_recv().unwrap(), frame);
+        assert_eq!(lwpkt.read().unwrap(), vec![package.clone()]);
+
+        for _ in 0..2 {
+            lwpkt.write(package.clone()).unwrap();
+        }
+        assert_eq!(lwpkt.write(package), Err(crate::Error::ErrorMem));
+
+        raw_pkt.write_all(&frame).unwrap();
+        assert_eq!(lwpkt.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", feature = "heapless"))]
+    fn read_into_array_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        for cmd in 0..3 {
+            lwpkt
+                .write(crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: vec![],
+                })
+                .unwrap();
+        }
+
+        let (packets, more) = lwpkt.read_into_array::<2>().unwrap();
+        assert_eq!(packets.iter().map(|p| p.cmd).collect::<Vec<_>>(), [0, 1]);
+        assert!(more);
+
+        let (packets, more) = lwpkt.read_into_array::<2>().unwrap();
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].cmd, 2);
+        assert!(!more);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn try_write_test() {
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: b"retry me".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+
+        let remaining = loop {
+            match lwpkt.try_write(package.clone()).unwrap() {
+                crate::Sent::All => {}
+                crate::Sent::Partial { queued, remaining } => {
+                    assert_eq!(queued, 0);
+                    break remaining;
+                }
+            }
+        };
+        assert_eq!(remaining, frame);
+
+        raw_pkt.from_pkt.try_recv().unwrap();
+        assert_eq!(lwpkt.try_write_frame(remaining), Ok(crate::Sent::All));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn write_all_or_nothing_test() {
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: vec![0x42; 200],
+        };
+
+        let mut written = 0;
+        let err = loop {
+            match lwpkt.write(package.clone()) {
+                Ok(()) => written += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err, crate::Error::ErrorMem);
+
+        let mut bytes = vec![];
+        while let Ok(chunk) = raw_pkt.from_pkt.try_recv() {
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let decoded = crate::decode_all(&bytes);
+        assert_eq!(decoded.len(), written);
+        assert!(
+            decoded
+                .iter()
+                .all(|p| p.as_ref().unwrap().data == package.data)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn wide_length_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        assert!(
+            lwpkt.max_data_len() >= 256,
+            "LWPKT_CFG_MAX_DATA_LEN too small for a two-byte length"
+        );
+
+        let data = (0..256).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data,
+        };
+
+        // 256 needs two varint groups, `80 02`
+        let frame = lwpkt.encode(&package).unwrap();
+        let header = frame.len() - 256 - 1 - usize::from(crate::config().use_crc);
+        assert_eq!(frame[header - 2..header], [0x80, 0x02]);
+
+        lwpkt.feed(&frame).unwrap();
+        assert_eq!(lwpkt.read().unwrap(), vec![package]);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn write_flush_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"flushed".to_vec(),
+        };
+
+        for _ in 0..LwPkt::CHANNEL_CAPACITY {
+            lwpkt.write(package.clone()).unwrap();
+        }
+        assert_eq!(lwpkt.write(package.clone()), Err(crate::Error::ErrorMem));
+
+        // Waits for the raw side to make room
+        let reader = tokio::spawn(async move {
+            let first = raw_pkt.read_chunk().await.unwrap();
+            (first, raw_pkt)
+        });
+        lwpkt.write_flush(package.clone()).await.unwrap();
+
+        let (first, raw_pkt) = reader.await.unwrap();
+        assert_eq!(first, lwpkt.encode(&package).unwrap());
+        drop(raw_pkt);
+        assert_eq!(
+            lwpkt.write_flush(package).await,
+            Err(crate::Error::ErrorClosedRaw)
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn spawn_pump_test() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut lwpkt, raw_pkt) = LwPkt::new(LwRb::new(1024), LwRb::new(1024)).unwrap();
+        let (transport, mut remote) = tokio::io::duplex(1024);
+        let pump = crate::spawn_pump(raw_pkt, transport);
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"pumped".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+
+        lwpkt.write(package.clone()).unwrap();
+        let mut wire = vec![0u8; frame.len()];
+        remote.read_exact(&mut wire).await.unwrap();
+        assert_eq!(wire, frame);
+
+        remote.write_all(&frame).await.unwrap();
+        assert_eq!(lwpkt.recv_one().await.unwrap(), package);
+
+        drop(remote);
+        pump.await.unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn chunked_test() {
+        let rb = LwRb::new(4096);
+        let wb = LwRb::new(4096);
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        let max = LwPkt::MAX_PACKAGE_SIZE as usize;
+        let data: Vec<u8> = (0..max * 2 + 10).map(|v| v as u8).collect();
+
+        assert_eq!(lwpkt.write_chunked(0x11, 0x85, &data).unwrap(), 3);
+
+        while let Ok(frame) = raw_pkt.from_pkt.try_recv() {
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        let mut reassembler = crate::Reassembler::new();
+        let complete: Vec<_> = lwpkt
+            .read()
+            .unwrap()
+            .into_iter()
+            .filter_map(|p| reassembler.push(p))
+            .collect();
+
+        assert_eq!(complete.len(), 1);
+        assert_eq!(complete[0].cmd, 0x85);
+        assert_eq!(complete[0].data, data);
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-crc")))]
+    fn crc_test() {
+        let rb = LwRb::new(1024);
+        let wb = LwRb::new(1024);
+
+        let (mut lwpkt, mut raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec(),
+            })
+            .unwrap();
+
+        let mut buffer = vec![];
+        raw_pkt.read_to_end(&mut buffer).unwrap();
+
+        // start, ..., crc, stop
+        let crc = buffer[buffer.len() - 2];
+        assert_eq!(crate::crc(&buffer[1..buffer.len() - 2]), crc);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn cmd_out_of_range_test() {
+        let rb = LwRb::new(1024);
+        let wb = LwRb::new(1024);
+
+        let (mut lwpkt, _raw_pkt) = LwPkt::new(rb, wb).unwrap();
+
+        let res = lwpkt.write(crate::Package {
+            cmd: 0x1FF,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: b"some hello".to_vec(),
+        });
+
+        if lwpkt.max_cmd() == 0xFF {
+            assert_eq!(
+                res,
+                Err(crate::Error::CmdOutOfRange {
+                    cmd: 0x1FF,
+                    max: 0xFF
+                })
+            );
+        } else {
+            assert_eq!(res, Ok(()));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn read_upto_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+
+        for cmd in 1..=3 {
+            lwpkt
+                .write(crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0x11,
+                    flags: 0,
+                    data: b"some hello".to_vec(),
+                })
+                .unwrap();
+        }
+
+        while let Ok(frame) = raw_pkt.from_pkt.try_recv() {
+            raw_pkt.write_all(&frame).unwrap();
+        }
+
+        let first = lwpkt.read_upto(1).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].cmd, 1);
+
+        let rest = lwpkt.read().unwrap();
+        assert_eq!(rest.iter().map(|p| p.cmd).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "async",
+        not(feature = "no-crc"),
+        not(feature = "no-addr"),
+        not(feature = "flags")
+    ))]
+    fn foreign_frame_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::with_sizes(1024, 1024).unwrap();
+
+        let data: Vec<u8> = (0..200).map(|v| v as u8).collect();
+
+        // Assembled by hand from the wire format, length 200 is the varint `C8 01`
+        let mut frame = vec![0xAA, 0x12, 0x11, 0x85, 0xC8, 0x01];
+        frame.extend_from_slice(&data);
+        frame.extend_from_slice(&[0x64, 0x55]);
+
+        raw_pkt.write_all(&frame).unwrap();
+
+        assert_eq!(
+            lwpkt.read().unwrap(),
+            vec![crate::Package {
                 cmd: 0x85,
                 from: 0x12,
                 to: 0x11,
-                data: b"some hello".to_vec()
-            }
-        )
+                flags: 0,
+                data,
+            }]
+        );
+    }
+
+    #[test]
+    #[cfg(all(
+        not(feature = "no-crc"),
+        not(feature = "no-addr"),
+        not(feature = "flags")
+    ))]
+    fn verify_frame_test() {
+        let data: Vec<u8> = (0..200).map(|v| v as u8).collect();
+
+        let mut frame = vec![0xAA, 0x12, 0x11, 0x85, 0xC8, 0x01];
+        frame.extend_from_slice(&data);
+        frame.extend_from_slice(&[0x64, 0x55]);
+
+        let package = crate::verify_frame(&frame).unwrap();
+        assert_eq!(package.from, 0x12);
+        assert_eq!(package.data, data);
+
+        // Length 3 padded with an empty varint group, decodes but is never encoded that way
+        let mut frame = vec![0xAA, 0x12, 0x11, 0x85, 0x83, 0x00, b'a', b'b', b'c'];
+        frame.push(crate::crc(&frame[1..]));
+        frame.push(0x55);
+
+        assert_eq!(crate::decode_all(&frame).len(), 1);
+        match crate::verify_frame(&frame) {
+            Err(crate::Error::FrameMismatch { offset, .. }) => assert_eq!(offset, 4),
+            e => panic!("unexpected {e:?}"),
+        }
+
+        assert_eq!(
+            crate::verify_frame(&frame[..4]),
+            Err(crate::Error::WaitData)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "tokio-codec")]
+    fn codec_test() {
+        use tokio_util::codec::{Decoder, Encoder};
+
+        let mut codec = crate::LwPktCodec::with_sizes(1024, 1024).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: b"some hello".to_vec(),
+        };
+
+        let mut buffer = bytes::BytesMut::new();
+        codec.encode(package, &mut buffer).unwrap();
+
+        // Partial frame
+        let mut head = buffer.split_to(4);
+        assert_eq!(codec.decode(&mut head).unwrap(), None);
+
+        let decoded = codec.decode(&mut buffer).unwrap().unwrap();
+        assert_eq!(decoded.cmd, 0x85);
+        assert_eq!(decoded.to, 0x11);
+        assert!(decoded.data_eq(b"some hello"));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn loopback_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0x11,
+            flags: 0,
+            data: b"some hello".to_vec(),
+        };
+        lwpkt.write(package).unwrap();
+
+        let s = lwpkt.read().unwrap();
+        assert_eq!(s.len(), 1);
+        assert!(s[0].data_eq(b"some hello"));
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn framing_bytes_test() {
+        let config = crate::config();
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let package = crate::Package {
+            cmd: 0x85,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: b"framed".to_vec(),
+        };
+        let frame = lwpkt.encode(&package).unwrap();
+        assert_eq!(frame.first(), Some(&config.start_byte));
+        assert_eq!(frame.last(), Some(&config.stop_byte));
+
+        lwpkt.feed(&frame).unwrap();
+        let s = lwpkt.read().unwrap();
+        assert_eq!(s.len(), 1);
+        assert_eq!(s[0].data, package.data);
+    }
+
+    #[test]
+    fn connection_test() {
+        // VecDeque reads back what was written to it, a loopback transport
+        let mut connection =
+            crate::Connection::with_sizes(std::collections::VecDeque::new(), 1024, 1024).unwrap();
+
+        assert_eq!(connection.poll().unwrap(), 0);
+        assert_eq!(connection.recv(), None);
+
+        for cmd in [0x11, 0x12] {
+            connection
+                .send(crate::Package {
+                    cmd,
+                    from: 0,
+                    to: 0,
+                    flags: 0,
+                    data: b"over the wire".to_vec(),
+                })
+                .unwrap();
+            assert!(!connection.transport().is_empty());
+
+            assert_eq!(connection.poll().unwrap(), 1);
+            let package = connection.recv().unwrap();
+            assert_eq!(package.cmd, cmd);
+            assert!(package.data_eq(b"over the wire"));
+        }
+
+        assert_eq!(connection.recv(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn empty_payload_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let ack = crate::Package {
+            cmd: 0x06,
+            from: 0,
+            to: 0,
+            flags: 0,
+            data: vec![],
+        };
+        lwpkt.write(ack).unwrap();
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x07,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![],
+            })
+            .unwrap();
+
+        let s = lwpkt.read().unwrap();
+        assert_eq!(s.len(), 2);
+        assert_eq!(s[0].cmd, 0x06);
+        assert!(s[0].data.is_empty());
+        assert_eq!(s[1].cmd, 0x07);
+        assert!(s[1].data.is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn decode_all_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec(),
+            })
+            .unwrap();
+
+        let mut bytes = vec![0x00, 0x13];
+        bytes.extend_from_slice(&frame);
+        bytes.extend_from_slice(&frame[..frame.len() - 1]);
+        bytes.push(0x00);
+        bytes.extend_from_slice(&frame);
+
+        let results = crate::decode_all(&bytes);
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 1);
+
+        // Garbage must never panic
+        let garbage: Vec<u8> = (0..4096u32).map(|v| (v * 7919 % 251) as u8).collect();
+        crate::decode_all(&garbage);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-addr"))]
+    fn oversized_len_test() {
+        // Length varint `FF 7F` is 16383, far beyond any buffer
+        let mut frame = vec![0xAA, 0x12, 0x11];
+        #[cfg(feature = "flags")]
+        frame.push(0x00);
+        frame.extend_from_slice(&[0x85, 0xFF, 0x7F]);
+        frame.extend_from_slice(&[0x55; 64]);
+
+        let results = crate::decode_all(&frame);
+        assert!(!results.is_empty());
+        assert_eq!(results[0], Err(crate::Error::ErrorMem));
+        assert!(results.iter().all(|r| r.is_err()));
+    }
+
+    #[test]
+    #[cfg(all(feature = "async", not(feature = "no-addr")))]
+    fn builder_test() {
+        let (mut lwpkt, mut raw_pkt) = LwPkt::builder()
+            .read_buffer(LwRb::new(512))
+            .address(0x12)
+            .build()
+            .unwrap();
+
+        assert_eq!(lwpkt.address(), 0x12);
+        assert_eq!(lwpkt.read_buffer_size(), 512);
+
+        lwpkt
+            .write(crate::Package {
+                cmd: 0x85,
+                from: 0,
+                to: 0x11,
+                flags: 0,
+                data: b"some hello".to_vec(),
+            })
+            .unwrap();
+
+        let mut buffer = vec![];
+        raw_pkt.read_to_end(&mut buffer).unwrap();
+        assert_eq!(buffer[1], 0x12);
+    }
+
+    #[test]
+    #[cfg(feature = "async")]
+    fn bytes_needed_test() {
+        let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+        let frame = lwpkt
+            .encode(&crate::Package {
+                cmd: 0x11,
+                from: 0,
+                to: 0,
+                flags: 0,
+                data: vec![0u8; 10],
+            })
+            .unwrap();
+        assert_eq!(lwpkt.bytes_needed(), None);
+
+        // Everything up to and including the length byte plus 4 bytes of payload
+        let trailer = 1 + usize::from(crate::config().use_crc);
+        let head = frame.len() - trailer - 10;
+        lwpkt.feed(&frame[..head + 4]).unwrap();
+        assert_eq!(lwpkt.bytes_needed(), Some(frame.len() - head - 4));
+
+        lwpkt.feed(&frame[head + 4..frame.len() - 1]).unwrap();
+        assert_eq!(lwpkt.bytes_needed(), Some(1));
+
+        lwpkt.feed(&frame[frame.len() - 1..]).unwrap();
+        assert_eq!(lwpkt.bytes_needed(), None);
+        assert_eq!(lwpkt.read().unwrap().len(), 1);
+    }
+
+    #[cfg(all(feature = "async", feature = "testing", not(feature = "no-addr")))]
+    proptest::proptest! {
+        #[test]
+        fn roundtrip_proptest(package in proptest::prelude::any::<crate::Package>()) {
+            let mut lwpkt = LwPkt::new_loopback(LwRb::new(1024), LwRb::new(1024)).unwrap();
+
+            let frame = lwpkt.encode(&package).unwrap();
+            lwpkt.feed(&frame).unwrap();
+
+            let decoded = lwpkt.read().unwrap();
+            proptest::prop_assert_eq!(
+                decoded,
+                vec![crate::Package {
+                    from: lwpkt.address(),
+                    ..package
+                }]
+            );
+        }
     }
 }
@@ -0,0 +1,102 @@
+use std::sync::{Arc, Mutex};
+
+use crate::Package;
+
+/// Payload buffers handed back by dropped [`PooledPackage`]s and chunks read by the
+/// [`crate::LwPktRaw`], reused by the [`crate::LwPkt`] owning the pool for decoded packets and
+/// [`crate::LwPkt::write_if_space`] instead of allocating a new one every time
+#[derive(Clone, Default)]
+pub struct PacketPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl PacketPool {
+    /// Buffers kept at most, the ones returned past it are freed
+    pub const CAPACITY: usize = 32;
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buffers waiting to be reused
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Empty buffer, recycled when one is available
+    pub(crate) fn take(&self) -> Vec<u8> {
+        self.lock().pop().unwrap_or_default()
+    }
+
+    pub(crate) fn put(&self, mut buffer: Vec<u8>) {
+        buffer.clear();
+
+        let mut buffers = self.lock();
+        if buffers.len() < Self::CAPACITY && buffer.capacity() > 0 {
+            buffers.push(buffer);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        // A panic while holding the lock cannot leave the list half updated
+        self.buffers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl std::fmt::Debug for PacketPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PacketPool")
+            .field("buffers", &self.len())
+            .finish()
+    }
+}
+
+/// [`Package`] returning its payload buffer to the pool it came from when dropped
+#[derive(Debug)]
+pub struct PooledPackage {
+    package: Package,
+    pool: PacketPool,
+}
+
+impl PooledPackage {
+    pub(crate) fn new(package: Package, pool: PacketPool) -> Self {
+        Self { package, pool }
+    }
+
+    /// Keeps the payload buffer out of the pool
+    pub fn into_package(mut self) -> Package {
+        let data = std::mem::take(&mut self.package.data);
+
+        Package {
+            cmd: self.package.cmd,
+            from: self.package.from,
+            to: self.package.to,
+            flags: self.package.flags,
+            data,
+        }
+    }
+}
+
+impl std::ops::Deref for PooledPackage {
+    type Target = Package;
+
+    fn deref(&self) -> &Self::Target {
+        &self.package
+    }
+}
+
+impl std::ops::DerefMut for PooledPackage {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.package
+    }
+}
+
+impl Drop for PooledPackage {
+    fn drop(&mut self) {
+        self.pool.put(std::mem::take(&mut self.package.data));
+    }
+}
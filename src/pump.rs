@@ -0,0 +1,48 @@
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+use crate::LwPktRaw;
+
+/// Bytes read from the transport at once
+const READ_SIZE: usize = 1024;
+
+/// Moves bytes between `raw` and `transport` in both directions on a background task, the
+/// application only deals with the [`crate::LwPkt`] half. The task ends once the
+/// transport reaches EOF or fails, or the [`crate::LwPkt`] is dropped.
+pub fn spawn_pump<T>(raw: LwPktRaw, transport: T) -> JoinHandle<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let (mut reader, mut writer) = tokio::io::split(transport);
+        let sender = raw.sender();
+        let mut raw = raw;
+
+        let inbound = async move {
+            let mut buffer = vec![0u8; READ_SIZE];
+            loop {
+                let len = match reader.read(&mut buffer).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => len,
+                };
+
+                if sender.write_chunk(buffer[..len].to_vec()).await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        let outbound = async move {
+            while let Ok(chunk) = raw.read_chunk().await {
+                if writer.write_all(&chunk).await.is_err() || writer.flush().await.is_err() {
+                    break;
+                }
+            }
+        };
+
+        tokio::select! {
+            _ = inbound => {}
+            _ = outbound => {}
+        }
+    })
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use crate::{LwPkt, Package};
+
+/// Collects the frames produced by [`LwPkt::write_chunked`] back into whole payloads.
+///
+/// A payload ends with the first frame shorter than the max length, [`LwPkt::MAX_PACKAGE_SIZE`]
+/// unless built with [`Reassembler::with_max_len`]. Frames of different commands may be
+/// interleaved.
+#[derive(Debug)]
+pub struct Reassembler {
+    partial: HashMap<u32, Vec<u8>>,
+    max_len: usize,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::with_max_len(LwPkt::MAX_PACKAGE_SIZE as usize)
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// For a sender capped with [`LwPkt::set_max_data_len`]
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            partial: HashMap::new(),
+            max_len,
+        }
+    }
+
+    /// Returns the complete package once its last frame is pushed
+    pub fn push(&mut self, package: Package) -> Option<Package> {
+        let last = package.data.len() < self.max_len;
+
+        let data = self.partial.entry(package.cmd).or_default();
+        data.extend_from_slice(&package.data);
+
+        if !last {
+            return None;
+        }
+
+        let data = self.partial.remove(&package.cmd).unwrap_or_default();
+
+        Some(Package { data, ..package })
+    }
+
+    pub fn clear(&mut self) {
+        self.partial.clear();
+    }
+}
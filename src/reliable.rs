@@ -0,0 +1,225 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use async_channel::{Receiver, Sender};
+
+use crate::{Error, LwPkt, Package};
+
+struct Unacked {
+    package: Package,
+    sent_at: Instant,
+    retries: u32,
+    done: Sender<Result<(), Error>>,
+}
+
+/// Resolves once the peer acknowledged the packet, or failed after the last retransmission
+pub struct AckHandle {
+    seq: u8,
+    done: Receiver<Result<(), Error>>,
+}
+
+impl AckHandle {
+    pub fn seq(&self) -> u8 {
+        self.seq
+    }
+
+    pub async fn acked(self) -> Result<(), Error> {
+        self.done.recv().await.map_err(|_| Error::ErrorClosedRaw)?
+    }
+
+    /// `None` while the packet is still waiting for its ACK
+    pub fn try_acked(&self) -> Option<Result<(), Error>> {
+        match self.done.try_recv() {
+            Ok(res) => Some(res),
+            Err(async_channel::TryRecvError::Empty) => None,
+            Err(async_channel::TryRecvError::Closed) => Some(Err(Error::ErrorClosedRaw)),
+        }
+    }
+}
+
+/// Acknowledged delivery on top of [`LwPkt`], both ends need one.
+///
+/// The first payload byte carries a sequence number, the peer answers every packet with an
+/// `ack_cmd` packet holding that sequence number. Packets not acknowledged within the
+/// timeout are sent again, up to `max_retries` times. Retransmitted duplicates are
+/// acknowledged again but handed out once.
+///
+/// ACKs and retransmissions that cannot be written are tried again on the next
+/// [`ReliableLink::poll`], see [`ReliableLink::take_send_error`].
+pub struct ReliableLink {
+    lwpkt: LwPkt,
+    ack_cmd: u32,
+    timeout: Duration,
+    max_retries: u32,
+    next_seq: u8,
+    unacked: HashMap<u8, Unacked>,
+    // Last sequence number handed out per sender address
+    last_seen: HashMap<u8, u8>,
+    // ACKs not written yet, oldest first
+    acks: VecDeque<Package>,
+    send_error: Option<Error>,
+}
+
+impl ReliableLink {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_millis(200);
+    pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+    /// `ack_cmd` is reserved for acknowledgements and must not be used by the application
+    pub fn new(lwpkt: LwPkt, ack_cmd: u32) -> Self {
+        Self {
+            lwpkt,
+            ack_cmd,
+            timeout: Self::DEFAULT_TIMEOUT,
+            max_retries: Self::DEFAULT_MAX_RETRIES,
+            next_seq: 0,
+            unacked: HashMap::new(),
+            last_seen: HashMap::new(),
+            acks: VecDeque::new(),
+            send_error: None,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// Sends `package` with the next sequence number, the payload must leave room for it.
+    /// Fails with [`Error::SeqInFlight`] while that sequence number still waits for its ACK.
+    pub fn send_reliable(&mut self, mut package: Package) -> Result<AckHandle, Error> {
+        if package.cmd == self.ack_cmd {
+            return Err(Error::AckCmdReserved { cmd: package.cmd });
+        }
+
+        let seq = self.next_seq;
+        if self.unacked.contains_key(&seq) {
+            return Err(Error::SeqInFlight { seq });
+        }
+
+        package.data.insert(0, seq);
+        self.lwpkt.write(package.clone())?;
+        self.next_seq = self.next_seq.wrapping_add(1);
+
+        let (done, rx) = async_channel::bounded(1);
+        let unacked = Unacked {
+            package,
+            sent_at: Instant::now(),
+            retries: 0,
+            done,
+        };
+        self.unacked.insert(seq, unacked);
+
+        Ok(AckHandle { seq, done: rx })
+    }
+
+    /// Reads incoming packets, answers and resolves ACKs and retransmits what timed out.
+    /// Returns the application packets with the sequence number stripped, failing writes
+    /// do not affect them.
+    pub fn poll(&mut self) -> Result<Vec<Package>, Error> {
+        let mut received = vec![];
+
+        for mut package in self.lwpkt.read()? {
+            if package.data.is_empty() {
+                log::warn!("Packet without sequence number, cmd {:#x}", package.cmd);
+                continue;
+            }
+
+            let seq = package.data.remove(0);
+
+            if package.cmd == self.ack_cmd {
+                if let Some(unacked) = self.unacked.remove(&seq) {
+                    let _ = unacked.done.try_send(Ok(()));
+                }
+                continue;
+            }
+
+            self.acks.push_back(Package {
+                cmd: self.ack_cmd,
+                from: 0,
+                to: package.from,
+                flags: 0,
+                data: vec![seq],
+            });
+
+            if self.last_seen.insert(package.from, seq) != Some(seq) {
+                received.push(package);
+            }
+        }
+
+        self.flush_acks();
+        self.retransmit();
+
+        Ok(received)
+    }
+
+    /// First error hit while writing ACKs or retransmissions since the last call
+    pub fn take_send_error(&mut self) -> Option<Error> {
+        self.send_error.take()
+    }
+
+    fn flush_acks(&mut self) {
+        while let Some(ack) = self.acks.pop_front() {
+            if let Err(e) = self.lwpkt.write(ack.clone()) {
+                self.acks.push_front(ack);
+                self.send_error.get_or_insert(e);
+                break;
+            }
+        }
+    }
+
+    fn retransmit(&mut self) {
+        let now = Instant::now();
+
+        let expired = self
+            .unacked
+            .iter()
+            .filter(|(_, unacked)| now.duration_since(unacked.sent_at) >= self.timeout)
+            .map(|(seq, _)| *seq)
+            .collect::<Vec<_>>();
+
+        for seq in expired {
+            let Some(unacked) = self.unacked.get_mut(&seq) else {
+                continue;
+            };
+
+            if unacked.retries >= self.max_retries {
+                if let Some(unacked) = self.unacked.remove(&seq) {
+                    let _ = unacked.done.try_send(Err(Error::AckTimeout { seq }));
+                }
+                continue;
+            }
+
+            // A retransmission that could not be written is not counted
+            match self.lwpkt.write(unacked.package.clone()) {
+                Ok(()) => {
+                    unacked.retries += 1;
+                    unacked.sent_at = now;
+                }
+                Err(e) => {
+                    self.send_error.get_or_insert(e);
+                }
+            }
+        }
+    }
+
+    /// Packets sent and not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        self.unacked.len()
+    }
+
+    pub fn get_ref(&self) -> &LwPkt {
+        &self.lwpkt
+    }
+
+    pub fn get_mut(&mut self) -> &mut LwPkt {
+        &mut self.lwpkt
+    }
+
+    /// Pending [`AckHandle`]s resolve to [`Error::ErrorClosedRaw`], queued ACKs are dropped
+    pub fn into_inner(self) -> LwPkt {
+        self.lwpkt
+    }
+}
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use crate::Package;
+
+/// Reported by [`SequenceTracker::track`] for a packet that is not the next one expected
+/// from its sender
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SequenceEvent {
+    /// Packets between `expected` and `got` were lost, tracking resumes after `got`
+    Gap { from: u8, expected: u8, got: u8 },
+    /// `got` was already passed, a late or duplicated packet
+    OutOfOrder { from: u8, expected: u8, got: u8 },
+}
+
+/// Follows a wrapping `u8` sequence number per sender address. Where the number sits in the
+/// payload is up to the application, `extract` pulls it out of a decoded packet.
+///
+/// A number up to 127 ahead of the expected one counts as a gap, anything behind it as out
+/// of order. The first packet of a sender only sets the starting point.
+pub struct SequenceTracker<F> {
+    extract: F,
+    // Next sequence number expected per sender address
+    expected: HashMap<u8, u8>,
+}
+
+impl<F: FnMut(&Package) -> Option<u8>> SequenceTracker<F> {
+    pub fn new(extract: F) -> Self {
+        Self {
+            extract,
+            expected: HashMap::new(),
+        }
+    }
+
+    /// `None` for an in order packet, or one `extract` found no sequence number in
+    pub fn track(&mut self, package: &Package) -> Option<SequenceEvent> {
+        let got = (self.extract)(package)?;
+        let from = package.from;
+
+        let expected = self.expected.insert(from, got.wrapping_add(1))?;
+
+        match got.wrapping_sub(expected) {
+            0 => None,
+            1..=127 => Some(SequenceEvent::Gap {
+                from,
+                expected,
+                got,
+            }),
+            _ => {
+                // Stay on the sequence, a late packet does not move it back
+                self.expected.insert(from, expected);
+                Some(SequenceEvent::OutOfOrder {
+                    from,
+                    expected,
+                    got,
+                })
+            }
+        }
+    }
+
+    /// Forgets every sender, their next packet sets a new starting point
+    pub fn reset(&mut self) {
+        self.expected.clear();
+    }
+}
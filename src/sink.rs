@@ -0,0 +1,80 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use futures_sink::Sink;
+
+use crate::{Error, LwPkt, Package};
+
+type SendFuture = Pin<Box<dyn Future<Output = Result<(), Error>> + Send>>;
+
+/// Outgoing packets as a `Sink`, see [`LwPkt::into_sink`]. Every packet is sent as one
+/// chunk and `poll_ready` waits until the raw channel accepted the previous one.
+pub struct PacketSink {
+    lwpkt: LwPkt,
+    sending: Option<SendFuture>,
+}
+
+// Pinning is never projected to the fields: `sending` is pinned on its own in its box and
+// `lwpkt` is only used through `&mut`, moving a `PacketSink` never moves a pinned value. The
+// raw channel receivers inside `LwPkt` being `!Unpin` does not matter here.
+impl Unpin for PacketSink {}
+
+impl PacketSink {
+    pub(crate) fn new(lwpkt: LwPkt) -> Self {
+        Self {
+            lwpkt,
+            sending: None,
+        }
+    }
+
+    /// A packet still waiting for room in the raw channel is lost
+    pub fn into_inner(self) -> LwPkt {
+        self.lwpkt
+    }
+
+    fn poll_sending(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        if let Some(sending) = self.sending.as_mut() {
+            let res = ready!(sending.as_mut().poll(cx));
+            self.sending = None;
+
+            return Poll::Ready(res);
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<Package> for PacketSink {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_sending(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Package) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+
+        let frame = this.lwpkt.encode(&item)?;
+        let to_raw = this.lwpkt.transport.to_raw.clone();
+
+        this.sending = Some(Box::pin(async move {
+            to_raw.send(frame).await.map_err(|_| Error::ErrorClosedRaw)
+        }));
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.get_mut().poll_sending(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+
+        ready!(this.poll_sending(cx))?;
+        this.lwpkt.transport.to_raw.close();
+
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,68 @@
+use std::cell::UnsafeCell;
+
+use crate::{LwRb, ffi};
+
+// lwrb only lets the writer move `w_ptr` and the reader move `r_ptr`, a single producer and a
+// single consumer never touch the same field
+struct SharedRb(UnsafeCell<LwRb>);
+
+// With atomics lwrb publishes every pointer update to the other thread
+#[cfg(feature = "atomic")]
+unsafe impl Sync for SharedRb {}
+
+#[cfg(feature = "atomic")]
+type Shared = std::sync::Arc<SharedRb>;
+#[cfg(not(feature = "atomic"))]
+type Shared = std::rc::Rc<SharedRb>;
+
+/// Write half of a [`LwRb`], see [`LwRb::split`]
+pub struct RbProducer {
+    rb: Shared,
+}
+
+/// Read half of a [`LwRb`], see [`LwRb::split`]
+pub struct RbConsumer {
+    rb: Shared,
+}
+
+impl LwRb {
+    /// Splits the buffer into a write-only and a read-only handle, lwrb supports one writer
+    /// and one reader working at the same time. The buffer is freed once both are dropped.
+    ///
+    /// The handles can be sent to other threads with the `atomic` feature only, lwrb is
+    /// built with `LWRB_DISABLE_ATOMIC` otherwise and nothing orders the pointer updates
+    /// between threads.
+    pub fn split(self) -> (RbProducer, RbConsumer) {
+        let rb = Shared::new(SharedRb(UnsafeCell::new(self)));
+
+        (RbProducer { rb: rb.clone() }, RbConsumer { rb })
+    }
+}
+
+impl RbProducer {
+    /// Returns how many bytes fit
+    pub fn write(&mut self, data: &[u8]) -> usize {
+        unsafe { ffi::lwrb_write(lwrb(&self.rb), data.as_ptr() as *const _, data.len()) }
+    }
+
+    /// Bytes that can be written right now
+    pub fn free(&self) -> usize {
+        unsafe { ffi::lwrb_get_free(lwrb(&self.rb)) }
+    }
+}
+
+impl RbConsumer {
+    /// Returns how many bytes were read into `data`
+    pub fn read(&mut self, data: &mut [u8]) -> usize {
+        unsafe { ffi::lwrb_read(lwrb(&self.rb), data.as_mut_ptr() as *mut _, data.len()) }
+    }
+
+    /// Bytes written and not read yet
+    pub fn used(&self) -> usize {
+        unsafe { ffi::lwrb_get_full(lwrb(&self.rb)) }
+    }
+}
+
+fn lwrb(rb: &Shared) -> *mut ffi::lwrb {
+    unsafe { &raw mut (*rb.0.get()).lwrb }
+}
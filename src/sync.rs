@@ -0,0 +1,149 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::{Error, LwPkt, RawTransport, Sent};
+
+type Queue = Arc<Mutex<VecDeque<Vec<u8>>>>;
+
+/// [`RawTransport`] over two plain queues, for purely synchronous users. Same behaviour as
+/// the default channels: at most [`LwPkt::CHANNEL_CAPACITY`] chunks queued per direction,
+/// and [`Error::ErrorClosedRaw`] once the [`SyncRaw`] is dropped.
+///
+/// Without the default `async` feature it is the transport of a plain [`LwPkt`] and
+/// `async-channel` is not a dependency, e.g. with `--no-default-features --features sync`.
+pub struct SyncTransport {
+    to_raw: Queue,
+    from_raw: Queue,
+}
+
+impl SyncTransport {
+    /// Transport for [`LwPkt::with_transport`] and the [`SyncRaw`] holding the other end
+    pub fn pair() -> (Self, SyncRaw) {
+        let to_raw = Queue::default();
+        let from_raw = Queue::default();
+
+        let raw = SyncRaw {
+            last_read: Vec::new(),
+            from_pkt: to_raw.clone(),
+            to_pkt: from_raw.clone(),
+        };
+
+        (Self { to_raw, from_raw }, raw)
+    }
+
+    fn is_raw_connected(&self) -> bool {
+        Arc::strong_count(&self.to_raw) > 1
+    }
+}
+
+impl RawTransport for SyncTransport {
+    fn send_bytes(&mut self, frame: Vec<u8>) -> Result<Sent, Error> {
+        if !self.is_raw_connected() {
+            return Err(Error::ErrorClosedRaw);
+        }
+
+        let mut queue = lock(&self.to_raw);
+        if queue.len() >= LwPkt::CHANNEL_CAPACITY {
+            return Ok(Sent::Partial {
+                queued: 0,
+                remaining: frame,
+            });
+        }
+        queue.push_back(frame);
+
+        Ok(Sent::All)
+    }
+
+    fn recv_bytes(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        match lock(&self.from_raw).pop_front() {
+            Some(chunk) => Ok(Some(chunk)),
+            // Chunks written before the raw side went away are still handed out
+            None if !self.is_raw_connected() => Err(Error::ErrorClosedRaw),
+            None => Ok(None),
+        }
+    }
+}
+
+impl std::fmt::Debug for SyncTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncTransport")
+            .field("to_raw", &lock(&self.to_raw).len())
+            .field("from_raw", &lock(&self.from_raw).len())
+            .finish()
+    }
+}
+
+/// Raw side of a [`SyncTransport`], the bytes are moved with [`std::io::Read`] and
+/// [`std::io::Write`]
+pub struct SyncRaw {
+    // Rest of a chunk that did not fit the last read
+    last_read: Vec<u8>,
+    from_pkt: Queue,
+    to_pkt: Queue,
+}
+
+impl SyncRaw {
+    /// `false` once the [`LwPkt`] half is dropped
+    pub fn is_pkt_connected(&self) -> bool {
+        Arc::strong_count(&self.to_pkt) > 1
+    }
+}
+
+impl std::io::Read for SyncRaw {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut readed = 0;
+
+        while readed < buf.len() {
+            if self.last_read.is_empty() {
+                match lock(&self.from_pkt).pop_front() {
+                    Some(chunk) => self.last_read = chunk,
+                    None => break,
+                }
+            }
+
+            let len = self.last_read.len().min(buf.len() - readed);
+            buf[readed..readed + len].copy_from_slice(&self.last_read[..len]);
+            self.last_read.drain(..len);
+            readed += len;
+        }
+
+        Ok(readed)
+    }
+}
+
+impl std::io::Write for SyncRaw {
+    /// Queues `buf` as a single chunk, a full queue fails with
+    /// [`std::io::ErrorKind::WouldBlock`]
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if !self.is_pkt_connected() {
+            return Err(Error::ErrorClosedRaw.into());
+        }
+
+        let mut queue = lock(&self.to_pkt);
+        if queue.len() >= LwPkt::CHANNEL_CAPACITY {
+            return Err(std::io::ErrorKind::WouldBlock.into());
+        }
+        queue.push_back(buf.to_vec());
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for SyncRaw {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SyncRaw")
+            .field("last_read", &self.last_read.len())
+            .field("from_pkt", &lock(&self.from_pkt).len())
+            .field("to_pkt", &lock(&self.to_pkt).len())
+            .finish()
+    }
+}
+
+fn lock(queue: &Queue) -> MutexGuard<'_, VecDeque<Vec<u8>>> {
+    // A panic while holding the lock cannot leave the queue half updated
+    queue.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,56 @@
+#[cfg(feature = "async")]
+use async_channel::{Receiver, Sender, WeakReceiver};
+
+use crate::{Error, Sent};
+
+/// Carries the framed bytes of an [`crate::LwPkt`] to and from the other end
+pub trait RawTransport {
+    /// Hands a complete frame over, a transport with no room for it now returns
+    /// [`Sent::Partial`] with the bytes it did not take
+    fn send_bytes(&mut self, frame: Vec<u8>) -> Result<Sent, Error>;
+
+    /// Next chunk of received bytes, `None` when nothing is available right now
+    fn recv_bytes(&mut self) -> Result<Option<Vec<u8>>, Error>;
+}
+
+/// Default transport, a pair of bounded channels with the [`crate::LwPktRaw`] on the other
+/// side
+#[cfg(feature = "async")]
+pub struct ChannelTransport {
+    pub(crate) to_raw: Sender<Vec<u8>>,
+    pub(crate) from_raw: Receiver<Vec<u8>>,
+    // Receiving end of `to_raw`, weak so dropping `LwPktRaw` still closes the channel
+    pub(crate) outgoing: WeakReceiver<Vec<u8>>,
+}
+
+#[cfg(feature = "async")]
+impl RawTransport for ChannelTransport {
+    fn send_bytes(&mut self, frame: Vec<u8>) -> Result<Sent, Error> {
+        match self.to_raw.try_send(frame) {
+            Ok(_) => Ok(Sent::All),
+            Err(async_channel::TrySendError::Full(remaining)) => Ok(Sent::Partial {
+                queued: 0,
+                remaining,
+            }),
+            Err(async_channel::TrySendError::Closed(_)) => Err(Error::ErrorClosedRaw),
+        }
+    }
+
+    fn recv_bytes(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        match self.from_raw.try_recv() {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(async_channel::TryRecvError::Empty) => Ok(None),
+            Err(async_channel::TryRecvError::Closed) => Err(Error::ErrorClosedRaw),
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl std::fmt::Debug for ChannelTransport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelTransport")
+            .field("to_raw", &self.to_raw.len())
+            .field("from_raw", &self.from_raw.len())
+            .finish()
+    }
+}